@@ -0,0 +1,152 @@
+//! Text encodings of byte arrays.
+
+#[cfg(not(feature = "std"))]
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fmt;
+
+/// The text encoding of byte arrays.
+///
+/// Byte arrays, e.g. fields with `#[serde(with = "serde_bytes")]`, are
+/// serialized as a string in this encoding. The encoding is selected with
+/// [`Serializer::bytes_encoding`] and [`Deserializer::bytes_encoding`] and
+/// defaults to [`Hex`](BytesEncoding::Hex).
+///
+/// [`Serializer::bytes_encoding`]: crate::Serializer::bytes_encoding
+/// [`Deserializer::bytes_encoding`]: crate::Deserializer::bytes_encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BytesEncoding {
+    /// Two lowercase hexadecimal digits per byte. Uppercase digits are
+    /// accepted when decoding.
+    #[default]
+    Hex,
+    /// Base64 with the standard alphabet and padding. Padding is optional
+    /// when decoding.
+    Base64,
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl BytesEncoding {
+    pub(crate) fn encode(self, v: &[u8]) -> String {
+        let mut s = String::new();
+        match self {
+            BytesEncoding::Hex => {
+                for b in v {
+                    s.push(char::from_digit((b >> 4) as u32, 16).unwrap());
+                    s.push(char::from_digit((b & 15) as u32, 16).unwrap());
+                }
+            }
+            BytesEncoding::Base64 => {
+                for chunk in v.chunks(3) {
+                    let n = chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+                    for i in 0..4 {
+                        if i <= chunk.len() {
+                            s.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+                        } else {
+                            s.push('=');
+                        }
+                    }
+                }
+            }
+        }
+        s
+    }
+
+    /// Returns `None` if `s` is not a valid encoding.
+    pub(crate) fn decode(self, s: &str) -> Option<Vec<u8>> {
+        match self {
+            BytesEncoding::Hex => {
+                if s.len() % 2 != 0 {
+                    return None;
+                }
+                s.as_bytes()
+                    .chunks(2)
+                    .map(|pair| {
+                        let hi = (pair[0] as char).to_digit(16)?;
+                        let lo = (pair[1] as char).to_digit(16)?;
+                        Some((hi << 4 | lo) as u8)
+                    })
+                    .collect()
+            }
+            BytesEncoding::Base64 => {
+                let s = s.as_bytes();
+                let s = match s.len() % 4 {
+                    0 if s.ends_with(b"==") => &s[..s.len() - 2],
+                    0 if s.ends_with(b"=") => &s[..s.len() - 1],
+                    _ => s,
+                };
+                if s.len() % 4 == 1 {
+                    return None;
+                }
+                let mut v = Vec::with_capacity(s.len() * 3 / 4);
+                for chunk in s.chunks(4) {
+                    let mut n = 0u32;
+                    for (i, &ch) in chunk.iter().enumerate() {
+                        let digit = BASE64.iter().position(|&c| c == ch)? as u32;
+                        n |= digit << (18 - 6 * i);
+                    }
+                    // Every digit beyond the first encodes the next byte.
+                    for i in 0..chunk.len() - 1 {
+                        v.push((n >> (16 - 8 * i)) as u8);
+                    }
+                }
+                Some(v)
+            }
+        }
+    }
+}
+
+impl fmt::Display for BytesEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BytesEncoding::Hex => f.write_str("hex"),
+            BytesEncoding::Base64 => f.write_str("base64"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        let hex = BytesEncoding::Hex;
+        assert_eq!(hex.encode(b""), "");
+        assert_eq!(hex.encode(&[0, 1, 171, 255]), "0001abff");
+        assert_eq!(hex.decode("0001abff"), Some(vec![0, 1, 171, 255]));
+        assert_eq!(hex.decode("0001ABFF"), Some(vec![0, 1, 171, 255]));
+        assert_eq!(hex.decode(""), Some(vec![]));
+        assert_eq!(hex.decode("0"), None);
+        assert_eq!(hex.decode("0g"), None);
+        assert_eq!(hex.decode("+1"), None);
+    }
+
+    #[test]
+    fn test_base64() {
+        let base64 = BytesEncoding::Base64;
+        for (v, s) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+            (&[251, 255], "+/8="),
+        ] {
+            assert_eq!(base64.encode(v), s);
+            assert_eq!(base64.decode(s).as_deref(), Some(v));
+            assert_eq!(base64.decode(s.trim_end_matches('=')).as_deref(), Some(v));
+        }
+        assert_eq!(base64.decode("Z"), None);
+        assert_eq!(base64.decode("Zg="), None);
+        assert_eq!(base64.decode("Z==="), None);
+        assert_eq!(base64.decode("Zm9v="), None);
+        assert_eq!(base64.decode("Zm,v"), None);
+    }
+}
@@ -1,30 +1,431 @@
+use serde::de::value::BorrowedStrDeserializer;
 use serde::de::{
-    self, Deserialize, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+    self, Deserialize, DeserializeOwned, DeserializeSeed, EnumAccess, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
 };
 
-use crate::error::{Error, Result};
+use crate::bytes::BytesEncoding;
+use crate::error::{Error, ErrorKind, PathSegment, Result};
+use crate::expr::{self, Failure};
 use crate::util;
 
+use core::num::{IntErrorKind, ParseIntError};
+use core::ops::Range;
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    borrow::Cow,
+    collections::btree_map::{BTreeMap, Entry as MapEntry},
+    vec,
+};
+#[cfg(feature = "std")]
+use std::{
+    borrow::Cow,
+    collections::btree_map::{BTreeMap, Entry as MapEntry},
+    vec,
+};
+
 pub struct Deserializer<T> {
     input: T,
+    options: Options,
+}
+
+// Options of the `Deserializer`, shared by all parts of the input.
+#[derive(Clone, Copy, Default)]
+struct Options {
+    bytes: BytesEncoding,
+    extended_integers: bool,
+    expressions: bool,
+    bare_flags: bool,
+    positional_fields: bool,
+    dotted_keys: bool,
+    repeated_keys: bool,
+    deny_duplicate_keys: bool,
+    verbatim_scalars: bool,
+}
+
+// Positions of all opening curly braces in the input, in increasing order,
+// and the positions of the matching closing braces, or `usize::MAX` for
+// unmatched braces. The index allows for skipping over protected parts of the
+// input in constant time, such that the input is scanned only once, regardless
+// of the nesting depth.
+struct Index {
+    opens: Vec<usize>,
+    closes: Vec<usize>,
+}
+
+// The maximum number of nested maps, sequences and variants inferred by
+// `deserialize_any`.
+const MAX_DEPTH: usize = 128;
+
+// A part of the input, delimited by byte positions `start` and `end`.
+#[derive(Clone, Copy)]
+struct Part<'a, 'de> {
+    input: &'de str,
+    index: &'a Index,
+    options: &'a Options,
+    start: usize,
+    end: usize,
+    // the number of inferred values that enclose this part
+    depth: usize,
+}
+
+// Iterator over the parts of a `Part`, equivalent to `util::safesplit`.
+#[derive(Clone)]
+struct Split<'a, 'de> {
+    rest: Part<'a, 'de>,
+    sep: u8,
+    exhausted: bool,
+}
+
+struct DeserializeSequence<'a, 'b, 'de> {
+    iter: &'b mut Split<'a, 'de>,
+    index: usize,
+}
+struct DeserializeMap<'a, 'b, 'de> {
+    iter: &'b mut Split<'a, 'de>,
+    entry: Option<(&'de str, Entry<'a, 'de>)>,
+    // deserialize the keys as strings, for maps inferred by `deserialize_any`
+    str_keys: bool,
+    // the fields of the deserialized struct, empty for maps
+    fields: &'static [&'static str],
+    // the number of leading items assigned to fields by position, and
+    // whether an item with a key has been seen since
+    positional: usize,
+    named: bool,
+    // the items that are yet to be deserialized as part of a group, once the
+    // first grouped item is seen
+    groups: Option<Groups<'a, 'de>>,
+    // the spans of the keys seen so far, if duplicate keys are denied
+    seen: BTreeMap<&'de str, Range<usize>>,
 }
 
-struct DeserializeSequence<'a, 'b> {
-    iter: &'b mut util::SafesplitIter<'a>,
+// The items of a map that are grouped into a single entry.
+#[derive(Default)]
+struct Groups<'a, 'de> {
+    // the pairs `REST` and `VALUE` of the dotted keys `KEY.REST=VALUE`, by
+    // `KEY`
+    dotted: BTreeMap<&'de str, Vec<(Part<'a, 'de>, Part<'a, 'de>)>>,
+    // the pairs `KEY` and `VALUE` of the other items, by `KEY`, for repeated
+    // keys
+    repeated: BTreeMap<&'de str, Vec<(Part<'a, 'de>, Part<'a, 'de>)>>,
 }
-struct DeserializeMap<'a, 'b> {
-    iter: &'b mut util::SafesplitIter<'a>,
-    value: Option<&'a str>,
+
+// The value of a map entry.
+enum Entry<'a, 'de> {
+    Value(Part<'a, 'de>),
+    // A bare flag and its value.
+    Flag(Part<'a, 'de>, bool),
+    // The `KEY` and the pairs `REST` and `VALUE` of the dotted keys
+    // `KEY.REST=VALUE` with a common `KEY`.
+    Dotted(Part<'a, 'de>, Vec<(Part<'a, 'de>, Part<'a, 'de>)>),
+    // The pairs `KEY` and `VALUE` of the items `KEY=VALUE` with a repeated
+    // `KEY`.
+    Repeated(Vec<(Part<'a, 'de>, Part<'a, 'de>)>),
+}
+// The entries of a map as a map, or as a sequence if the keys are indices.
+struct DeserializeEntries<'a, 'de> {
+    iter: vec::IntoIter<(Part<'a, 'de>, Entry<'a, 'de>)>,
+    entry: Option<(&'de str, Entry<'a, 'de>)>,
+    str_keys: bool,
+    index: usize,
+    seen: BTreeMap<&'de str, Range<usize>>,
+}
+
+// The value of a bare flag `KEY` or `no-KEY`.
+struct DeserializeFlag<'a, 'de> {
+    item: Part<'a, 'de>,
+    value: bool,
 }
-struct DeserializeEnum<'a> {
-    variant: &'a str,
-    value: &'a str,
+// A `Variant{VALUE}` as a map with a single entry, for `deserialize_any`.
+struct DeserializeVariantMap<'a, 'de> {
+    variant: Option<Part<'a, 'de>>,
+    value: Option<Part<'a, 'de>>,
+}
+struct DeserializeEnum<'a, 'de> {
+    variant: Part<'a, 'de>,
+    value: Part<'a, 'de>,
 }
 
 #[allow(clippy::should_implement_trait)]
 impl<'de> Deserializer<&'de str> {
     pub fn from_str(input: &'de str) -> Self {
-        Deserializer { input }
+        Deserializer {
+            input,
+            options: Options::default(),
+        }
+    }
+
+    /// Sets the text encoding of byte arrays.
+    pub fn bytes_encoding(mut self, encoding: BytesEncoding) -> Self {
+        self.options.bytes = encoding;
+        self
+    }
+
+    /// Enables extended integer literals.
+    ///
+    /// Besides plain decimal integers, this accepts hexadecimal, octal and
+    /// binary integers with prefixes `0x`, `0o` and `0b`, and underscores
+    /// between digits, optionally preceded by a sign: `1_000_000`, `0xff`,
+    /// `-0b1010`, `+0o755`. Disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    /// let de = stringly::Deserializer::from_str("0o755,1_000_000,0xff");
+    /// let v = <(u32, u64, u8)>::deserialize(de.extended_integers(true)).unwrap();
+    /// assert_eq!(v, (493, 1000000, 255));
+    /// ```
+    pub fn extended_integers(mut self, enabled: bool) -> Self {
+        self.options.extended_integers = enabled;
+        self
+    }
+
+    /// Enables arithmetic expressions for numbers.
+    ///
+    /// A number that is not a literal is evaluated as an expression of
+    /// literals, the constants `pi` and `e`, the operators `+`, `-`, `*`, `/`
+    /// and `**` (exponentiation) and parentheses. For integers, the
+    /// expression must consist of integer literals and operations with an
+    /// integer result; `/` rounds toward zero. An expression that cannot be
+    /// evaluated, or that is nested more than 128 levels deep, fails with the
+    /// span of the offending token. Types inferred by `deserialize_any` are not
+    /// affected. Disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    /// let de = stringly::Deserializer::from_str("1/64,2*pi,2**10");
+    /// let (dt, radius, n) = <(f64, f64, u32)>::deserialize(de.expressions(true)).unwrap();
+    /// assert_eq!((dt, radius, n), (0.015625, 2.0 * std::f64::consts::PI, 1024));
+    /// ```
+    pub fn expressions(mut self, enabled: bool) -> Self {
+        self.options.expressions = enabled;
+        self
+    }
+
+    /// Enables verbatim text for scalars of which the type is inferred.
+    ///
+    /// Serde buffers the values of untagged and internally tagged enums and
+    /// of structs with flattened fields before their type is known, see the
+    /// [crate documentation](crate#type-inference). By default, text that
+    /// looks like a boolean or a number is buffered as such, which suits
+    /// fields of these types but rejects `String` fields and loses the exact
+    /// text for [`Value`](crate::Value) fields. With this option enabled,
+    /// non-empty text is buffered verbatim, and only maps, enums and
+    /// sequences are still inferred. Buffered boolean and number fields are
+    /// then rejected. Disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    /// use std::collections::BTreeMap;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Run {
+    ///     nelems: u32,
+    ///     #[serde(flatten)]
+    ///     rest: BTreeMap<String, String>,
+    /// }
+    ///
+    /// let de = stringly::Deserializer::from_str("nelems=4,version=1.10,lazy=true");
+    /// let run = Run::deserialize(de.verbatim_scalars(true)).unwrap();
+    /// assert_eq!((run.rest["version"].as_str(), run.rest["lazy"].as_str()), ("1.10", "true"));
+    /// ```
+    pub fn verbatim_scalars(mut self, enabled: bool) -> Self {
+        self.options.verbatim_scalars = enabled;
+        self
+    }
+
+    /// Enables bare flags in maps and structs.
+    ///
+    /// An item `KEY` without a value is accepted for a `bool` value as
+    /// `KEY=True` and for an `Option` value, e.g. an `Option<()>` marker, as
+    /// `Some`. An item `no-KEY` negates the flag: it sets a `bool` to `false`
+    /// and an `Option` to `None`, unless `no-KEY` is itself a field of the
+    /// struct. Disabled by default.
+    ///
+    /// See [`Serializer::bare_flags`] for the serializer counterpart.
+    ///
+    /// [`Serializer::bare_flags`]: crate::Serializer::bare_flags
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Args {
+    ///     verbose: bool,
+    ///     cache: bool,
+    ///     tol: f64,
+    ///     dry_run: Option<()>,
+    /// }
+    ///
+    /// let de = stringly::Deserializer::from_str("verbose,no-cache,tol=1e-6,dry_run");
+    /// let args = Args::deserialize(de.bare_flags(true)).unwrap();
+    /// assert!(args.verbose && !args.cache && args.dry_run.is_some());
+    /// ```
+    pub fn bare_flags(mut self, enabled: bool) -> Self {
+        self.options.bare_flags = enabled;
+        self
+    }
+
+    /// Enables positional fields in structs.
+    ///
+    /// Leading items without a key are assigned to the fields of a struct or
+    /// struct variant in declaration order, as in `Gmres{30,tol=1e-8}` for
+    /// `Gmres { restart: u32, tol: f64 }`. The remaining items are key-value
+    /// pairs as usual. A field given both by position and by key is an error.
+    /// Positional items take precedence over [bare flags](Self::bare_flags).
+    /// Disabled by default.
+    ///
+    /// See [`Serializer::positional_fields`] for the serializer counterpart.
+    ///
+    /// [`Serializer::positional_fields`]: crate::Serializer::positional_fields
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize, PartialEq, Debug)]
+    /// enum Solver {
+    ///     Gmres { restart: u32, tol: f64 },
+    /// }
+    ///
+    /// let de = stringly::Deserializer::from_str("Gmres{30,tol=1e-8}");
+    /// let solver = Solver::deserialize(de.positional_fields(true)).unwrap();
+    /// assert_eq!(solver, Solver::Gmres { restart: 30, tol: 1e-8 });
+    /// ```
+    pub fn positional_fields(mut self, enabled: bool) -> Self {
+        self.options.positional_fields = enabled;
+        self
+    }
+
+    /// Enables dotted keys in maps and structs.
+    ///
+    /// Items `KEY.REST=VALUE` with a common `KEY` are grouped into a single
+    /// entry `KEY` whose value is the map of the items `REST=VALUE`, or the
+    /// sequence of the items if every `REST` starts with the index of the
+    /// element, counting from zero. The grouping applies recursively to
+    /// `REST`, such that `solver.tol=1e-6,meshes.0.nelems=4` is equivalent to
+    /// `solver={tol=1e-6},meshes={nelems=4}`. A key is not split at dots
+    /// enclosed in curly braces. Disabled by default.
+    ///
+    /// See [`Serializer::dotted_keys`] for the serializer counterpart.
+    ///
+    /// [`Serializer::dotted_keys`]: crate::Serializer::dotted_keys
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Solver {
+    ///     tol: f64,
+    ///     maxiter: u32,
+    /// }
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     solver: Solver,
+    ///     nelems: Vec<u32>,
+    /// }
+    ///
+    /// let s = "solver.tol=1e-6,nelems.0=4,solver.maxiter=100,nelems.1=8";
+    /// let de = stringly::Deserializer::from_str(s);
+    /// let config = Config::deserialize(de.dotted_keys(true)).unwrap();
+    /// assert_eq!((config.solver.tol, config.solver.maxiter), (1e-6, 100));
+    /// assert_eq!(config.nelems, [4, 8]);
+    /// ```
+    pub fn dotted_keys(mut self, enabled: bool) -> Self {
+        self.options.dotted_keys = enabled;
+        self
+    }
+
+    /// Enables repeated keys in maps and structs.
+    ///
+    /// The values of items `KEY=VALUE` with a repeated `KEY` are collected,
+    /// in order, into a single entry `KEY` whose value is the sequence of the
+    /// values, such that `input=a.msh,input=b.msh` is equivalent to
+    /// `input={a.msh,b.msh}`. A repeated key of a value that is not a
+    /// sequence is an error. Disabled by default.
+    ///
+    /// See [`Serializer::repeated_keys`] for the serializer counterpart.
+    ///
+    /// [`Serializer::repeated_keys`]: crate::Serializer::repeated_keys
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Args {
+    ///     input: Vec<String>,
+    ///     output: String,
+    /// }
+    ///
+    /// let s = "input=a.msh,output=c.msh,input=b.msh";
+    /// let de = stringly::Deserializer::from_str(s);
+    /// let args = Args::deserialize(de.repeated_keys(true)).unwrap();
+    /// assert_eq!(args.input, ["a.msh", "b.msh"]);
+    /// assert_eq!(args.output, "c.msh");
+    /// ```
+    pub fn repeated_keys(mut self, enabled: bool) -> Self {
+        self.options.repeated_keys = enabled;
+        self
+    }
+
+    /// Denies duplicate keys in maps and structs, at any nesting level.
+    ///
+    /// A key that occurs a second time, after unprotection, fails with
+    /// [`ErrorKind::DuplicateKey`], located at the second occurrence.
+    /// Repeated keys collected into a sequence by
+    /// [`repeated_keys`](Self::repeated_keys) and dotted keys with a common
+    /// prefix are not duplicates. Disabled by default, enabled by
+    /// [`strict`](Self::strict).
+    ///
+    /// [`ErrorKind::DuplicateKey`]: crate::ErrorKind::DuplicateKey
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    /// use std::collections::BTreeMap;
+    ///
+    /// let s = "a=1,b=2,a=3";
+    /// let map = BTreeMap::<String, u32>::deserialize(stringly::Deserializer::from_str(s));
+    /// assert_eq!(map.unwrap()["a"], 3);
+    /// let de = stringly::Deserializer::from_str(s).deny_duplicate_keys(true);
+    /// let error = BTreeMap::<String, u32>::deserialize(de).unwrap_err();
+    /// assert_eq!(error.to_string(), "duplicate key `a`, first given at bytes 0..1 (bytes 8..9)");
+    /// ```
+    pub fn deny_duplicate_keys(mut self, enabled: bool) -> Self {
+        self.options.deny_duplicate_keys = enabled;
+        self
+    }
+
+    /// Enables or disables the checks of the strict mode, which are currently
+    /// limited to [`deny_duplicate_keys`](Self::deny_duplicate_keys). Checks
+    /// can be changed individually after this call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    /// use std::collections::BTreeMap;
+    ///
+    /// let de = stringly::Deserializer::from_str("a=1,a=2").strict(true);
+    /// assert!(BTreeMap::<String, u32>::deserialize(de).is_err());
+    /// ```
+    pub fn strict(self, enabled: bool) -> Self {
+        self.deny_duplicate_keys(enabled)
     }
 }
 
@@ -36,24 +437,485 @@ where
     T::deserialize(Deserializer::from_str(s))
 }
 
+/// Deserializes an object from the multi-line form of Stringly.
+///
+/// See [`util::deprettify`] for a description of the multi-line form. The
+/// spans of errors refer to `s`.
+///
+/// # Examples
+///
+/// ```
+/// let v: Vec<(i32, i32)> = stringly::from_str_pretty("-\n  1\n  2\n-\n  3\n  4").unwrap();
+/// assert_eq!(v, [(1, 2), (3, 4)]);
+/// ```
+pub fn from_str_pretty<T>(s: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let (single, map) = util::deprettify_with_map(s)?;
+    from_str(&single).map_err(|error| {
+        error.map_span(|pos| {
+            // Map the position to the text of the last line that starts at or
+            // before it, or the end of that text if the position lies beyond.
+            let i = map.partition_point(|&[start, _, _]| start <= pos);
+            match i.checked_sub(1).map(|i| map[i]) {
+                Some([start, offset, len]) => offset + (pos - start).min(len),
+                None => 0,
+            }
+        })
+    })
+}
+
+impl Index {
+    fn new(input: &str) -> Self {
+        let mut opens = Vec::new();
+        let mut closes = Vec::new();
+        let mut stack = Vec::new();
+        for (i, ch) in input.bytes().enumerate() {
+            if ch == b'{' {
+                stack.push(opens.len());
+                opens.push(i);
+                closes.push(usize::MAX);
+            } else if ch == b'}' {
+                if let Some(k) = stack.pop() {
+                    closes[k] = i;
+                }
+            }
+        }
+        Index { opens, closes }
+    }
+
+    // Returns the position of the closing brace that matches the opening
+    // brace at position `open`.
+    fn matching(&self, open: usize) -> Option<usize> {
+        match self.opens.binary_search(&open) {
+            Ok(k) if self.closes[k] != usize::MAX => Some(self.closes[k]),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, 'de> Part<'a, 'de> {
+    fn parse_integer<T: Integer>(&self) -> Result<T> {
+        let s = self.as_str();
+        let (digits, radix) = if self.options.extended_integers {
+            match extended_integer(s) {
+                Some((digits, radix)) => (Cow::Owned(digits), radix),
+                None => (Cow::Borrowed(s), 10),
+            }
+        } else {
+            (Cow::Borrowed(s), 10)
+        };
+        let out_of_range = ErrorKind::IntegerOutOfRange {
+            ty: T::NAME,
+            min: T::MIN,
+            max: T::MAX,
+        };
+        let error = match T::from_str_radix(&digits, radix) {
+            Ok(v) => return Ok(v),
+            Err(error) => error,
+        };
+        let invalid = if T::MIN == 0 {
+            ErrorKind::NotAnUnsignedInteger
+        } else {
+            ErrorKind::NotAnInteger
+        };
+        match error.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => Err(self.error(out_of_range)),
+            _ if T::MIN == 0 && is_negative_integer(&digits, radix) => {
+                Err(self.error(ErrorKind::NegativeUnsignedInteger { ty: T::NAME }))
+            }
+            _ if self.options.expressions => {
+                match expr::eval_integer(s, self.options.extended_integers) {
+                    Ok(v) => T::from_i128(v).ok_or_else(|| {
+                        self.error(if v < 0 && T::MIN == 0 {
+                            ErrorKind::NegativeUnsignedInteger { ty: T::NAME }
+                        } else {
+                            out_of_range
+                        })
+                    }),
+                    Err(Failure::Invalid(span)) => Err(self.error_at(invalid, span)),
+                    Err(Failure::Overflow(span)) => Err(self.error_at(out_of_range, span)),
+                }
+            }
+            _ => Err(self.error(invalid)),
+        }
+    }
+
+    // Evaluates this part, which is not a floating point literal, as an
+    // expression if enabled.
+    fn eval_float(&self) -> Result<f64> {
+        if !self.options.expressions {
+            return Err(self.error(ErrorKind::NotAFloatingPointNumber));
+        }
+        expr::eval_float(self.as_str()).map_err(
+            |(Failure::Invalid(span) | Failure::Overflow(span))| {
+                self.error_at(ErrorKind::NotAFloatingPointNumber, span)
+            },
+        )
+    }
+
+    fn as_str(&self) -> &'de str {
+        &self.input[self.start..self.end]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Self {
+        Part {
+            start,
+            end,
+            ..*self
+        }
+    }
+
+    // Returns this part one level deeper, for inferring the values nested in
+    // it, or an error if it is nested too deeply.
+    fn nested(self) -> Result<Self> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(ErrorKind::NestedTooDeeply));
+        }
+        Ok(Part {
+            depth: self.depth + 1,
+            ..self
+        })
+    }
+
+    // Returns an error located at this part.
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error::from(kind).at(self.start..self.end)
+    }
+
+    // Returns an error located at `span`, relative to the start of this part.
+    fn error_at(&self, kind: ErrorKind, span: Range<usize>) -> Error {
+        Error::from(kind).at(self.start + span.start..self.start + span.end)
+    }
+
+    // Deserializes this part and locates any error that is not yet located.
+    fn deserialize_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+            .map_err(|error| error.at(self.start..self.end))
+    }
+
+    // Returns the position of the first `sep` that is not enclosed in curly
+    // braces, starting from position `start`.
+    fn find(&self, start: usize, sep: u8) -> Option<usize> {
+        let bytes = self.input.as_bytes();
+        let mut level: isize = 0;
+        let mut i = start;
+        while i < self.end {
+            #[cfg(test)]
+            tests::SCANNED.with(|n| n.set(n.get() + 1));
+            let ch = bytes[i];
+            if ch == sep && level == 0 {
+                return Some(i);
+            } else if ch == b'{' {
+                // A matched pair of braces does not contain separators at the
+                // current level, unless the current level is negative.
+                match self.index.matching(i) {
+                    Some(j) if j < self.end && level >= 0 => i = j,
+                    _ => level += 1,
+                }
+            } else if ch == b'}' {
+                level -= 1;
+            }
+            i += 1;
+        }
+        None
+    }
+
+    // Deserializes this part as a string, regardless of the type requested.
+    fn deserialize_str_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(BorrowedStrDeserializer::new(self.as_str()))
+            .map_err(|error: Error| error.at(self.start..self.end))
+    }
+
+    // Equivalent to `util::safesplit`.
+    fn split(self, sep: u8) -> Split<'a, 'de> {
+        Split {
+            rest: self,
+            sep,
+            exhausted: self.is_empty(),
+        }
+    }
+
+    // Equivalent to `util::safesplit_once`.
+    fn split_once(self, sep: u8) -> Option<(Self, Self)> {
+        self.find(self.start, sep)
+            .map(|i| (self.slice(self.start, i), self.slice(i + 1, self.end)))
+    }
+
+    // Equivalent to `util::unprotect`.
+    fn unprotect(self) -> Self {
+        let s = self.as_str();
+        let start = self.start + (util::unprotect(s).as_ptr() as usize - s.as_ptr() as usize);
+        self.slice(start, start + util::unprotect(s).len())
+    }
+
+    fn visit_map<V>(
+        self,
+        visitor: V,
+        str_keys: bool,
+        fields: &'static [&'static str],
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let mut iter = self.split(b',');
+        let v = visitor.visit_map(DeserializeMap {
+            iter: &mut iter,
+            entry: None,
+            str_keys,
+            fields,
+            positional: 0,
+            named: false,
+            groups: None,
+            seen: BTreeMap::new(),
+        })?;
+        match iter.next() {
+            None => Ok(v),
+            Some(part) => Err(part.error(ErrorKind::TooManyElements)),
+        }
+    }
+
+    // Equivalent to `util::splitarg`.
+    fn splitarg(self) -> Result<(Self, Self)> {
+        match self.as_str().find(['{', '}']).map(|i| self.start + i) {
+            Some(i) if self.input.as_bytes()[i] == b'}' => Err(self.error(ErrorKind::NotAnEnum)),
+            // The common case of a well formed enum, resolved using the index.
+            Some(i) if i > self.start && self.index.matching(i) == Some(self.end - 1) => Ok((
+                self.slice(self.start, i),
+                self.slice(i, self.end).unprotect(),
+            )),
+            Some(_) => {
+                let (variant, _) =
+                    util::splitarg(self.as_str()).map_err(|_| self.error(ErrorKind::NotAnEnum))?;
+                let i = self.start + variant.len();
+                Ok((
+                    self.slice(self.start, i),
+                    self.slice(i, self.end).unprotect(),
+                ))
+            }
+            None => Ok((self, self.slice(self.end, self.end))),
+        }
+    }
+}
+
+impl<'a, 'de> Iterator for Split<'a, 'de> {
+    type Item = Part<'a, 'de>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+        let rest = self.rest;
+        match rest.find(rest.start, self.sep) {
+            Some(i) => {
+                self.rest.start = i + 1;
+                Some(rest.slice(rest.start, i))
+            }
+            None => {
+                self.exhausted = true;
+                Some(rest)
+            }
+        }
+    }
+}
+
+// The primitive integer types.
+trait Integer: Sized {
+    const NAME: &'static str;
+    const MIN: i128;
+    const MAX: u128;
+
+    fn from_str_radix(s: &str, radix: u32) -> core::result::Result<Self, ParseIntError>;
+    fn from_i128(v: i128) -> Option<Self>;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ident)*) => {
+        $(
+            impl Integer for $ty {
+                const NAME: &'static str = stringify!($ty);
+                const MIN: i128 = $ty::MIN as i128;
+                const MAX: u128 = $ty::MAX as u128;
+
+                fn from_str_radix(s: &str, radix: u32) -> core::result::Result<Self, ParseIntError> {
+                    $ty::from_str_radix(s, radix)
+                }
+
+                fn from_i128(v: i128) -> Option<Self> {
+                    v.try_into().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8 i16 i32 i64 i128 u8 u16 u32 u64 u128);
+
+// Returns `true` if `s` is a minus sign followed by digits in base `radix`.
+fn is_negative_integer(s: &str, radix: u32) -> bool {
+    s.strip_prefix('-')
+        .is_some_and(|digits| !digits.is_empty() && digits.chars().all(|ch| ch.is_digit(radix)))
+}
+
+// Converts an extended integer literal, e.g. `-0x_ff`, into the sign and
+// digits without underscores, and the radix, or returns `None` if `s` is not
+// an extended integer literal.
+pub(crate) fn extended_integer(s: &str) -> Option<(String, u32)> {
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'-') => ("-", &s[1..]),
+        Some(b'+') => ("", &s[1..]),
+        _ => ("", s),
+    };
+    let (radix, rest) = match rest.get(..2) {
+        Some("0x" | "0X") => (16, &rest[2..]),
+        Some("0o" | "0O") => (8, &rest[2..]),
+        Some("0b" | "0B") => (2, &rest[2..]),
+        // An underscore must not precede the first digit of a decimal.
+        _ if rest.starts_with('_') => return None,
+        _ => (10, rest),
+    };
+    let mut digits = String::from(sign);
+    digits.extend(rest.chars().filter(|&ch| ch != '_'));
+    // The digits must not be empty or start with another sign.
+    if digits.len() == sign.len() || digits[sign.len()..].starts_with(['+', '-']) {
+        return None;
+    }
+    Some((digits, radix))
+}
+
+macro_rules! forward_to_part {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value>
+            where
+                V: Visitor<'de>,
+            {
+                let index = Index::new(self.input);
+                let part = Part {
+                    input: self.input,
+                    index: &index,
+                    options: &self.options,
+                    start: 0,
+                    end: self.input.len(),
+                    depth: 0,
+                };
+                de::Deserializer::$method(part, $($arg,)* visitor)
+                    .map_err(|error| error.at(part.start..part.end).rooted())
+            }
+        )*
+    };
+}
+
 impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     type Error = Error;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    forward_to_part! {
+        deserialize_any()
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_option()
+        deserialize_unit()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_map()
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+        deserialize_identifier()
+        deserialize_ignored_any()
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Part<'_, 'de> {
+    type Error = Error;
+
+    // Infers the type from the text, see the crate documentation.
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        let s = self.as_str();
+        // Only literals are inferred as numbers, not expressions.
+        let options = Options {
+            expressions: false,
+            ..*self.options
+        };
+        let literal = Part {
+            options: &options,
+            ..self
+        };
+        let scalars = !self.options.verbatim_scalars;
+        if s.is_empty() {
+            visitor.visit_unit()
+        } else if scalars && s.eq_ignore_ascii_case("true") {
+            visitor.visit_bool(true)
+        } else if scalars && s.eq_ignore_ascii_case("false") {
+            visitor.visit_bool(false)
+        } else if let Some(v) = literal.parse_integer().ok().filter(|_| scalars) {
+            visitor.visit_u64(v)
+        } else if let Some(v) = literal.parse_integer().ok().filter(|_| scalars) {
+            visitor.visit_i64(v)
+        } else if let Some(v) = s
+            .parse()
+            .ok()
+            .filter(|_| scalars && s.contains(|ch: char| ch.is_ascii_digit()))
+        {
+            visitor.visit_f64(v)
+        } else if self.split(b',').all(|item| item.split_once(b'=').is_some()) {
+            self.nested()?.visit_map(visitor, true, &[])
+        } else if let Some((variant, value)) = self
+            .splitarg()
+            .ok()
+            .filter(|(variant, _)| !variant.is_empty() && variant.end < self.end)
+        {
+            visitor.visit_map(DeserializeVariantMap {
+                variant: Some(variant),
+                value: Some(value.nested()?),
+            })
+        } else if self.find(self.start, b',').is_some() || util::unprotect(s) != s {
+            self.nested()?.deserialize_seq(visitor)
+        } else {
+            visitor.visit_borrowed_str(s)
+        }
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match &self.input.to_ascii_lowercase() as &str {
+        match &self.as_str().to_ascii_lowercase() as &str {
             "true" | "yes" => visitor.visit_bool(true),
             "false" | "no" => visitor.visit_bool(false),
-            _ => Err(Error::NotABoolean),
+            _ => Err(self.error(ErrorKind::NotABoolean)),
         }
     }
 
@@ -61,89 +923,79 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_i8(v),
-            Err(_) => Err(Error::NotAnInteger),
-        }
+        visitor.visit_i8(self.parse_integer()?)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_i16(v),
-            Err(_) => Err(Error::NotAnInteger),
-        }
+        visitor.visit_i16(self.parse_integer()?)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_i32(v),
-            Err(_) => Err(Error::NotAnInteger),
-        }
+        visitor.visit_i32(self.parse_integer()?)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_i64(v),
-            Err(_) => Err(Error::NotAnInteger),
-        }
+        visitor.visit_i64(self.parse_integer()?)
+    }
+
+    fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i128(self.parse_integer()?)
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_u8(v),
-            Err(_) => Err(Error::NotAnUnsignedInteger),
-        }
+        visitor.visit_u8(self.parse_integer()?)
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_u16(v),
-            Err(_) => Err(Error::NotAnUnsignedInteger),
-        }
+        visitor.visit_u16(self.parse_integer()?)
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_u32(v),
-            Err(_) => Err(Error::NotAnUnsignedInteger),
-        }
+        visitor.visit_u32(self.parse_integer()?)
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
-            Ok(v) => visitor.visit_u64(v),
-            Err(_) => Err(Error::NotAnUnsignedInteger),
-        }
+        visitor.visit_u64(self.parse_integer()?)
+    }
+
+    fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_u128(self.parse_integer()?)
     }
 
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
+        match self.as_str().parse() {
             Ok(v) => visitor.visit_f32(v),
-            Err(_) => Err(Error::NotAFloatingPointNumber),
+            Err(_) => visitor.visit_f32(self.eval_float()? as f32),
         }
     }
 
@@ -151,9 +1003,9 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        match self.input.parse() {
+        match self.as_str().parse() {
             Ok(v) => visitor.visit_f64(v),
-            Err(_) => Err(Error::NotAFloatingPointNumber),
+            Err(_) => visitor.visit_f64(self.eval_float()?),
         }
     }
 
@@ -161,10 +1013,10 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        let mut chars = self.input.chars();
+        let mut chars = self.as_str().chars();
         match (chars.next(), chars.next()) {
             (Some(ch), None) => visitor.visit_char(ch),
-            _ => Err(Error::NotASingleCharacter),
+            _ => Err(self.error(ErrorKind::NotASingleCharacter)),
         }
     }
 
@@ -172,37 +1024,46 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_borrowed_str(self.input)
+        visitor.visit_borrowed_str(self.as_str())
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_borrowed_str(self.input)
+        visitor.visit_borrowed_str(self.as_str())
     }
 
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_byte_buf(visitor)
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        let encoding = self.options.bytes;
+        match encoding.decode(self.as_str()) {
+            Some(v) => visitor.visit_byte_buf(v),
+            None => Err(self.error(ErrorKind::NotEncodedBytes(encoding))),
+        }
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input.len() {
-            0 => visitor.visit_none(),
-            _ => visitor.visit_some(Deserializer::from_str(util::unprotect(self.input))),
+        match self.is_empty() {
+            true => visitor.visit_none(),
+            false => {
+                let part = self.unprotect();
+                visitor
+                    .visit_some(part)
+                    .map_err(|error| error.at(part.start..part.end))
+            }
         }
     }
 
@@ -210,9 +1071,9 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        match self.input.len() {
-            0 => visitor.visit_unit(),
-            _ => Err(Error::UnexpectedValueForUnit),
+        match self.is_empty() {
+            true => visitor.visit_unit(),
+            false => Err(self.error(ErrorKind::UnexpectedValueForUnit)),
         }
     }
 
@@ -220,16 +1081,20 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        match self.input.len() {
-            0 => visitor.visit_unit(),
-            _ => Err(Error::UnexpectedValueForUnit),
+        match self.is_empty() {
+            true => visitor.visit_unit(),
+            false => Err(self.error(ErrorKind::UnexpectedValueForUnit)),
         }
     }
 
-    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        // A `Value` takes the text as is, rather than the inferred kind.
+        if name == crate::value::TOKEN {
+            return self.deserialize_str(visitor);
+        }
         visitor.visit_newtype_struct(self)
     }
 
@@ -237,11 +1102,14 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        let mut iter = util::safesplit(self.input, ',');
-        let v = visitor.visit_seq(DeserializeSequence { iter: &mut iter });
+        let mut iter = self.split(b',');
+        let v = visitor.visit_seq(DeserializeSequence {
+            iter: &mut iter,
+            index: 0,
+        })?;
         match iter.next() {
-            None => v,
-            Some(_) => Err(Error::TooManyElements),
+            None => Ok(v),
+            Some(part) => Err(part.error(ErrorKind::TooManyElements)),
         }
     }
 
@@ -249,12 +1117,7 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        let mut iter = util::safesplit(self.input, ',');
-        let v = visitor.visit_seq(DeserializeSequence { iter: &mut iter });
-        match iter.next() {
-            None => v,
-            Some(_) => Err(Error::TooManyElements),
-        }
+        self.deserialize_seq(visitor)
     }
 
     fn deserialize_tuple_struct<V>(
@@ -266,47 +1129,26 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        let mut iter = util::safesplit(self.input, ',');
-        let v = visitor.visit_seq(DeserializeSequence { iter: &mut iter });
-        match iter.next() {
-            None => v,
-            Some(_) => Err(Error::TooManyElements),
-        }
+        self.deserialize_seq(visitor)
     }
 
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let mut iter = util::safesplit(self.input, ',');
-        let v = visitor.visit_map(DeserializeMap {
-            iter: &mut iter,
-            value: None,
-        });
-        match iter.next() {
-            None => v,
-            Some(_) => Err(Error::TooManyElements),
-        }
+        self.visit_map(visitor, false, &[])
     }
 
     fn deserialize_struct<V>(
         self,
         _name: &'static str,
-        _fields: &'static [&'static str],
+        fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let mut iter = util::safesplit(self.input, ',');
-        let v = visitor.visit_map(DeserializeMap {
-            iter: &mut iter,
-            value: None,
-        });
-        match iter.next() {
-            None => v,
-            Some(_) => Err(Error::TooManyElements),
-        }
+        self.visit_map(visitor, false, fields)
     }
 
     fn deserialize_enum<V>(
@@ -318,7 +1160,7 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        let (variant, value) = util::splitarg(self.input)?;
+        let (variant, value) = self.splitarg()?;
         visitor.visit_enum(DeserializeEnum { variant, value })
     }
 
@@ -326,18 +1168,18 @@ impl<'de> de::Deserializer<'de> for Deserializer<&'de str> {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_borrowed_str(self.input)
+        visitor.visit_borrowed_str(self.as_str())
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_unit()
     }
 }
 
-impl<'de, 'b> SeqAccess<'de> for DeserializeSequence<'de, 'b> {
+impl<'de> SeqAccess<'de> for DeserializeSequence<'_, '_, 'de> {
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
@@ -345,15 +1187,258 @@ impl<'de, 'b> SeqAccess<'de> for DeserializeSequence<'de, 'b> {
         T: DeserializeSeed<'de>,
     {
         match self.iter.next() {
-            Some(s) => seed
-                .deserialize(Deserializer::from_str(util::unprotect(s)))
-                .map(Some),
+            Some(part) => {
+                let index = self.index;
+                self.index += 1;
+                part.unprotect()
+                    .deserialize_seed(seed)
+                    .map(Some)
+                    .map_err(|error| error.within(PathSegment::Index(index)))
+            }
             None => Ok(None),
         }
     }
 }
 
-impl<'de, 'b> MapAccess<'de> for DeserializeMap<'de, 'b> {
+impl<'a, 'de> DeserializeMap<'a, '_, 'de> {
+    // Returns the next item of the map, skipping grouped items that have been
+    // deserialized with a previous item.
+    fn next_item(&mut self) -> Option<(Part<'a, 'de>, Option<(Part<'a, 'de>, Part<'a, 'de>)>)> {
+        loop {
+            let part = self.iter.next()?;
+            let item = part.split_once(b'=');
+            let Some((key, _)) = item.filter(|&(key, _)| grouped(key)) else {
+                return Some((part, item));
+            };
+            let groups = match self.groups {
+                Some(ref mut groups) => groups,
+                None => self
+                    .groups
+                    .insert(Groups::new(core::iter::once(part).chain(self.iter.clone()))),
+            };
+            if groups.contains(key) {
+                return Some((part, item));
+            }
+        }
+    }
+}
+
+// Splits a key `KEY.REST` at the first dot that is not enclosed in curly
+// braces into the unprotected `KEY` and `REST`, if dotted keys are enabled.
+fn dotted<'a, 'de>(key: Part<'a, 'de>) -> Option<(Part<'a, 'de>, Part<'a, 'de>)> {
+    if !key.options.dotted_keys {
+        return None;
+    }
+    key.find(key.start, b'.').map(|i| {
+        (
+            key.slice(key.start, i).unprotect(),
+            key.slice(i + 1, key.end),
+        )
+    })
+}
+
+// Records the span of the unprotected key `key`, failing if the key has been
+// seen before and duplicate keys are denied.
+fn record_key<'de>(seen: &mut BTreeMap<&'de str, Range<usize>>, key: Part<'_, 'de>) -> Result<()> {
+    if !key.options.deny_duplicate_keys {
+        return Ok(());
+    }
+    match seen.entry(key.as_str()) {
+        MapEntry::Vacant(entry) => {
+            entry.insert(key.start..key.end);
+            Ok(())
+        }
+        MapEntry::Occupied(entry) => Err(key.error(ErrorKind::DuplicateKey {
+            key: key.as_str().to_string(),
+            first: entry.get().clone(),
+            second: key.start..key.end,
+        })),
+    }
+}
+
+// Returns `true` if the item with key `key` may be grouped with other items.
+fn grouped(key: Part) -> bool {
+    key.options.repeated_keys || dotted(key).is_some()
+}
+
+impl<'a, 'de> Groups<'a, 'de> {
+    fn new(items: impl Iterator<Item = Part<'a, 'de>>) -> Self {
+        let mut groups = Groups::default();
+        for (key, value) in items.filter_map(|item| item.split_once(b'=')) {
+            if let Some((head, rest)) = dotted(key) {
+                groups
+                    .dotted
+                    .entry(head.as_str())
+                    .or_default()
+                    .push((rest, value.unprotect()));
+            } else if key.options.repeated_keys {
+                let key = key.unprotect();
+                groups
+                    .repeated
+                    .entry(key.as_str())
+                    .or_default()
+                    .push((key, value.unprotect()));
+            }
+        }
+        groups
+    }
+
+    // Returns `true` if the group of the item with key `key` is yet to be
+    // deserialized.
+    fn contains(&self, key: Part) -> bool {
+        match dotted(key) {
+            Some((head, _)) => self.dotted.contains_key(head.as_str()),
+            None => self.repeated.contains_key(key.unprotect().as_str()),
+        }
+    }
+
+    // Removes the group of the item `KEY=VALUE` and returns the unprotected
+    // key and the entry of the group.
+    fn remove(
+        &mut self,
+        key: Part<'a, 'de>,
+        value: Part<'a, 'de>,
+    ) -> (Part<'a, 'de>, Entry<'a, 'de>) {
+        if let Some((head, _)) = dotted(key) {
+            let pairs = self.dotted.remove(head.as_str()).unwrap_or_default();
+            return (head, Entry::Dotted(head, pairs));
+        }
+        let key = key.unprotect();
+        match self.repeated.remove(key.as_str()) {
+            Some(pairs) if pairs.len() > 1 => (key, Entry::Repeated(pairs)),
+            _ => (key, Entry::Value(value.unprotect())),
+        }
+    }
+}
+
+// Groups the pairs `KEY=VALUE` into entries, where pairs `KEY.REST=VALUE`
+// with a common `KEY` and, if enabled, pairs with a repeated `KEY` form a
+// single entry, in the order of first appearance.
+fn entries<'a, 'de>(
+    pairs: Vec<(Part<'a, 'de>, Part<'a, 'de>)>,
+) -> Vec<(Part<'a, 'de>, Entry<'a, 'de>)> {
+    let mut entries = Vec::new();
+    let mut dotted_groups = BTreeMap::<_, usize>::new();
+    let mut repeated_groups = BTreeMap::<_, usize>::new();
+    for (key, value) in pairs {
+        if let Some((head, rest)) = dotted(key) {
+            match dotted_groups.get(head.as_str()) {
+                Some(&i) => match entries[i] {
+                    (_, Entry::Dotted(_, ref mut pairs)) => pairs.push((rest, value)),
+                    _ => unreachable!(),
+                },
+                None => {
+                    dotted_groups.insert(head.as_str(), entries.len());
+                    entries.push((head, Entry::Dotted(head, vec![(rest, value)])));
+                }
+            }
+            continue;
+        }
+        let key = key.unprotect();
+        match repeated_groups.get(key.as_str()) {
+            Some(&i) if key.options.repeated_keys => match entries[i] {
+                (first_key, Entry::Value(first)) => {
+                    entries[i].1 = Entry::Repeated(vec![(first_key, first), (key, value)]);
+                }
+                (_, Entry::Repeated(ref mut pairs)) => pairs.push((key, value)),
+                _ => unreachable!(),
+            },
+            _ => {
+                repeated_groups.insert(key.as_str(), entries.len());
+                entries.push((key, Entry::Value(value)));
+            }
+        }
+    }
+    entries
+}
+
+impl<'a, 'de> Entry<'a, 'de> {
+    fn deserialize_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self {
+            Entry::Value(value) => value.deserialize_seed(seed),
+            Entry::Flag(item, value) => seed
+                .deserialize(DeserializeFlag { item, value })
+                .map_err(|error| error.at(item.start..item.end)),
+            Entry::Dotted(key, pairs) => seed
+                .deserialize(DeserializeDotted { key, pairs })
+                .map_err(|error| error.at(key.start..key.end)),
+            Entry::Repeated(pairs) => seed.deserialize(DeserializeRepeated { pairs }),
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for DeserializeMap<'_, '_, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let Some((part, item)) = self.next_item() else {
+            return Ok(None);
+        };
+        if item.is_none()
+            && !self.named
+            && part.options.positional_fields
+            && !self.fields.is_empty()
+        {
+            let field = *self
+                .fields
+                .get(self.positional)
+                .ok_or_else(|| part.error(ErrorKind::TooManyElements))?;
+            self.positional += 1;
+            self.entry = Some((field, Entry::Value(part.unprotect())));
+            return seed
+                .deserialize(BorrowedStrDeserializer::new(field))
+                .map(Some);
+        }
+        self.named = true;
+        let (key, entry) = match item {
+            Some((key, value)) => match self.groups {
+                Some(ref mut groups) => groups.remove(key, value),
+                None => (key.unprotect(), Entry::Value(value.unprotect())),
+            },
+            None if part.options.bare_flags => {
+                let key = part.unprotect();
+                match key.as_str().strip_prefix("no-") {
+                    Some(_) if !self.fields.contains(&key.as_str()) => {
+                        let key = key.slice(key.start + 3, key.end);
+                        (key, Entry::Flag(part, false))
+                    }
+                    _ => (key, Entry::Flag(part, true)),
+                }
+            }
+            None => return Err(part.error(ErrorKind::NotAKeyValuePair)),
+        };
+        if let Some(&field) = self.fields[..self.positional]
+            .iter()
+            .find(|&&field| field == key.as_str())
+        {
+            return Err(key.error(ErrorKind::PositionalAndNamedField { field }));
+        }
+        record_key(&mut self.seen, key)?;
+        self.entry = Some((key.as_str(), entry));
+        match self.str_keys {
+            true => key.deserialize_str_seed(seed).map(Some),
+            false => key.deserialize_seed(seed).map(Some),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let (key, entry) = self.entry.take().ok_or(ErrorKind::ValueBeforeKey)?;
+        entry
+            .deserialize_seed(seed)
+            .map_err(|error| error.within(PathSegment::Key(key.into())))
+    }
+}
+
+impl<'de> MapAccess<'de> for DeserializeEntries<'_, 'de> {
     type Error = Error;
 
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
@@ -361,14 +1446,14 @@ impl<'de, 'b> MapAccess<'de> for DeserializeMap<'de, 'b> {
         K: DeserializeSeed<'de>,
     {
         match self.iter.next() {
-            Some(s) => match util::safesplit_once(s, '=') {
-                Ok((key, value)) => {
-                    self.value = Some(value);
-                    seed.deserialize(Deserializer::from_str(util::unprotect(key)))
-                        .map(Some)
+            Some((key, entry)) => {
+                record_key(&mut self.seen, key)?;
+                self.entry = Some((key.as_str(), entry));
+                match self.str_keys {
+                    true => key.deserialize_str_seed(seed).map(Some),
+                    false => key.deserialize_seed(seed).map(Some),
                 }
-                Err(_) => Err(Error::NotAKeyValuePair),
-            },
+            }
             None => Ok(None),
         }
     }
@@ -377,19 +1462,453 @@ impl<'de, 'b> MapAccess<'de> for DeserializeMap<'de, 'b> {
     where
         V: DeserializeSeed<'de>,
     {
-        match self.value {
-            Some(s) => {
-                self.value = None;
-                seed.deserialize(Deserializer::from_str(util::unprotect(s)))
+        let (key, entry) = self.entry.take().ok_or(ErrorKind::ValueBeforeKey)?;
+        entry
+            .deserialize_seed(seed)
+            .map_err(|error| error.within(PathSegment::Key(key.into())))
+    }
+}
+
+impl<'de> SeqAccess<'de> for DeserializeEntries<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((_, entry)) => {
+                let index = self.index;
+                self.index += 1;
+                entry
+                    .deserialize_seed(seed)
+                    .map(Some)
+                    .map_err(|error| error.within(PathSegment::Index(index)))
             }
-            None => {
-                panic! {"next_key_seed not called before next_value_seed"}
+            None => Ok(None),
+        }
+    }
+}
+
+// The value of the dotted keys `KEY.REST=VALUE` with a common `KEY`, as the
+// map, struct or sequence of the pairs `REST=VALUE`.
+struct DeserializeDotted<'a, 'de> {
+    key: Part<'a, 'de>,
+    pairs: Vec<(Part<'a, 'de>, Part<'a, 'de>)>,
+}
+
+impl<'a, 'de> DeserializeDotted<'a, 'de> {
+    fn visit_map<V>(self, visitor: V, str_keys: bool) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_map(DeserializeEntries {
+            iter: entries(self.pairs).into_iter(),
+            entry: None,
+            str_keys,
+            index: 0,
+            seen: BTreeMap::new(),
+        })
+    }
+
+    fn visit_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let mut entries: Vec<_> = entries(self.pairs)
+            .into_iter()
+            .map(|(key, entry)| (key.as_str().parse::<usize>().ok(), key, entry))
+            .collect();
+        entries.sort_by_key(|&(index, _, _)| index.unwrap_or(usize::MAX));
+        for (i, &(index, key, _)) in entries.iter().enumerate() {
+            if index != Some(i) {
+                return Err(key.error(ErrorKind::NotAnIndex { expected: i }));
+            }
+        }
+        visitor.visit_seq(DeserializeEntries {
+            iter: entries
+                .into_iter()
+                .map(|(_, key, entry)| (key, entry))
+                .collect::<Vec<_>>()
+                .into_iter(),
+            entry: None,
+            str_keys: false,
+            index: 0,
+            seen: BTreeMap::new(),
+        })
+    }
+}
+
+macro_rules! not_dotted {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* _visitor: V) -> Result<V::Value>
+            where
+                V: Visitor<'de>,
+            {
+                Err(self.key.error(ErrorKind::UnexpectedDottedKeys))
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for DeserializeDotted<'_, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(mut self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        for (key, value) in &mut self.pairs {
+            *key = key.nested()?;
+            *value = value.nested()?;
+        }
+        self.visit_map(visitor, true)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_map(visitor, false)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_map(visitor, false)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    not_dotted! {
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_unit()
+        deserialize_unit_struct(_name: &'static str)
+        deserialize_enum(_name: &'static str, _variants: &'static [&'static str])
+        deserialize_identifier()
+    }
+}
+
+// The values of the items `KEY=VALUE` with a repeated `KEY`, as a sequence.
+struct DeserializeRepeated<'a, 'de> {
+    pairs: Vec<(Part<'a, 'de>, Part<'a, 'de>)>,
+}
+
+impl<'de> DeserializeRepeated<'_, 'de> {
+    fn visit_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let entries: Vec<_> = self
+            .pairs
+            .into_iter()
+            .map(|(key, value)| (key, Entry::Value(value)))
+            .collect();
+        visitor.visit_seq(DeserializeEntries {
+            iter: entries.into_iter(),
+            entry: None,
+            str_keys: false,
+            index: 0,
+            seen: BTreeMap::new(),
+        })
+    }
+}
+
+macro_rules! not_repeated {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* _visitor: V) -> Result<V::Value>
+            where
+                V: Visitor<'de>,
+            {
+                // Located at the first repetition.
+                let key = self.pairs[1].0;
+                Err(key.error(ErrorKind::RepeatedKey {
+                    key: key.as_str().to_string(),
+                }))
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for DeserializeRepeated<'_, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    not_repeated! {
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_unit()
+        deserialize_unit_struct(_name: &'static str)
+        deserialize_map()
+        deserialize_struct(_name: &'static str, _fields: &'static [&'static str])
+        deserialize_enum(_name: &'static str, _variants: &'static [&'static str])
+        deserialize_identifier()
+    }
+}
+
+macro_rules! not_a_flag {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* _visitor: V) -> Result<V::Value>
+            where
+                V: Visitor<'de>,
+            {
+                Err(self.item.error(ErrorKind::NotAKeyValuePair))
             }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for DeserializeFlag<'_, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_bool(self.value)
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_bool(self.value)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            true => visitor.visit_some(self),
+            false => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            true => visitor.visit_unit(),
+            false => Err(self.item.error(ErrorKind::NotAKeyValuePair)),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    not_a_flag! {
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_seq()
+        deserialize_tuple(_len: usize)
+        deserialize_tuple_struct(_name: &'static str, _len: usize)
+        deserialize_map()
+        deserialize_struct(_name: &'static str, _fields: &'static [&'static str])
+        deserialize_enum(_name: &'static str, _variants: &'static [&'static str])
+        deserialize_identifier()
+    }
+}
+
+impl<'de> MapAccess<'de> for DeserializeVariantMap<'_, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.variant.take() {
+            Some(variant) => variant.deserialize_str_seed(seed).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match (self.variant, self.value.take()) {
+            (None, Some(value)) => value.deserialize_seed(seed),
+            _ => Err(ErrorKind::ValueBeforeKey.into()),
         }
     }
 }
 
-impl<'de> EnumAccess<'de> for DeserializeEnum<'de> {
+impl<'de> EnumAccess<'de> for DeserializeEnum<'_, 'de> {
     type Error = Error;
     type Variant = Self;
 
@@ -397,20 +1916,17 @@ impl<'de> EnumAccess<'de> for DeserializeEnum<'de> {
     where
         V: DeserializeSeed<'de>,
     {
-        Ok((
-            seed.deserialize(Deserializer::from_str(self.variant))?,
-            self,
-        ))
+        Ok((self.variant.deserialize_seed(seed)?, self))
     }
 }
 
-impl<'de> VariantAccess<'de> for DeserializeEnum<'de> {
+impl<'de> VariantAccess<'de> for DeserializeEnum<'_, 'de> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
-        match self.value.len() {
-            0 => Ok(()),
-            _ => Err(Error::UnexpectedValueForUnit),
+        match self.value.is_empty() {
+            true => Ok(()),
+            false => Err(self.value.error(ErrorKind::UnexpectedValueForUnit)),
         }
     }
 
@@ -418,29 +1934,39 @@ impl<'de> VariantAccess<'de> for DeserializeEnum<'de> {
     where
         T: DeserializeSeed<'de>,
     {
-        seed.deserialize(Deserializer::from_str(self.value))
+        self.value.deserialize_seed(seed)
     }
 
     fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        de::Deserializer::deserialize_seq(Deserializer::from_str(self.value), visitor)
+        let value = self.value;
+        de::Deserializer::deserialize_seq(value, visitor)
+            .map_err(|error| error.at(value.start..value.end))
     }
 
-    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        de::Deserializer::deserialize_map(Deserializer::from_str(self.value), visitor)
+        let value = self.value;
+        de::Deserializer::deserialize_struct(value, "", fields, visitor)
+            .map_err(|error| error.at(value.start..value.end))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::cell::Cell;
     use serde::Deserialize;
 
+    thread_local! {
+        // The number of bytes scanned by `Part::find`.
+        pub(super) static SCANNED: Cell<usize> = const { Cell::new(0) };
+    }
+
     #[test]
     fn test_struct() {
         #[derive(Deserialize, PartialEq, Debug)]
@@ -483,4 +2009,83 @@ mod tests {
         let expected = E::Struct { a: 1 };
         assert_eq!(expected, from_str(j).unwrap());
     }
+
+    #[test]
+    fn test_index() {
+        let chars = ['{', '}', '<', '>', ',', '=', 'a'];
+        let mut s = String::new();
+        for length in 0..7u32 {
+            for i in 0..chars.len().pow(length) {
+                s.clear();
+                let mut k = i;
+                for _j in 0..length {
+                    s.push(chars[k % chars.len()]);
+                    k /= chars.len();
+                }
+                let index = Index::new(&s);
+                let part = Part {
+                    input: &s,
+                    index: &index,
+                    options: &Options::default(),
+                    start: 0,
+                    end: s.len(),
+                    depth: 0,
+                };
+                assert!(part
+                    .split(b',')
+                    .map(|part| part.as_str())
+                    .eq(util::safesplit(&s, ',')));
+                assert_eq!(
+                    part.split_once(b'=')
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                    util::safesplit_once(&s, '=').ok()
+                );
+                assert_eq!(part.unprotect().as_str(), util::unprotect(&s));
+                for start in 0..=s.len() {
+                    for end in start..=s.len() {
+                        assert!(part
+                            .slice(start, end)
+                            .split(b'=')
+                            .map(|part| part.as_str())
+                            .eq(util::safesplit(&s[start..end], '=')));
+                    }
+                }
+                assert_eq!(
+                    part.splitarg()
+                        .map(|(variant, value)| (variant.as_str(), value.as_str()))
+                        .map_err(|error| (error.span(), error.into_kind())),
+                    util::splitarg(&s).map_err(|_| (Some(0..s.len()), ErrorKind::NotAnEnum))
+                );
+            }
+        }
+    }
+    // Deserializes pathologically nested input and asserts that every byte is
+    // scanned at most once, regardless of the nesting depth.
+    #[test]
+    fn test_linear_scaling() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        enum Tree {
+            Leaf,
+            Node(Box<Tree>, Box<Tree>),
+        }
+
+        for depth in [1, 10, 200] {
+            let s = [
+                "Node{".repeat(depth),
+                "Leaf".to_string(),
+                ",Leaf}".repeat(depth),
+            ]
+            .concat();
+            SCANNED.with(|n| n.set(0));
+            from_str::<Tree>(&s).unwrap();
+            let scanned = SCANNED.with(Cell::get);
+            assert!(
+                scanned <= s.len(),
+                "scanned {} bytes of input of length {}",
+                scanned,
+                s.len()
+            );
+        }
+    }
 }
@@ -1,66 +1,375 @@
-use crate::util::SplitArgError;
+use crate::bytes::BytesEncoding;
+use crate::util::{self, SplitArgError};
 use serde::{de, ser};
 
 #[cfg(not(feature = "std"))]
-use core::{convert, fmt, result};
+use core::{convert, fmt, ops::Range, result};
 #[cfg(feature = "std")]
-use std::{convert, fmt, result};
+use std::{convert, fmt, ops::Range, result};
 
 /// Alias for a [`Result`] with the error type [`stringly::Error`].
 ///
 /// [`Result`]: https://doc.rust-lang.org/std/result/enum.Result.html
-/// [`stringly::Error`]: enum.Error.html
+/// [`stringly::Error`]: struct.Error.html
 pub type Result<T> = result::Result<T, Error>;
 
+/// An error, optionally located in the deserialized input.
+///
+/// Errors raised by the deserializer carry the byte range of the offending
+/// part of the input, see [`span`](Error::span), and the sequence of keys and
+/// indices that lead to it, see [`path`](Error::path).
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    span: Option<Range<usize>>,
+    path: Vec<PathSegment>,
+}
+
+/// The kind of an [`Error`].
 #[derive(Debug, PartialEq)]
-pub enum Error {
+pub enum ErrorKind {
     Message(String),
     NotABoolean,
     NotAnInteger,
     NotAnUnsignedInteger,
+    IntegerOutOfRange {
+        ty: &'static str,
+        min: i128,
+        max: u128,
+    },
+    NegativeUnsignedInteger {
+        ty: &'static str,
+    },
     NotAFloatingPointNumber,
     NotASingleCharacter,
     NotAnEnum,
     NotAKeyValuePair,
     UnexpectedValueForUnit,
     TooManyElements,
-    IndentTooSmall { lineno: usize },
-    UnmatchedUnindent { lineno: usize },
+    NestedTooDeeply,
+    PositionalAndNamedField {
+        field: &'static str,
+    },
+    NotAnIndex {
+        expected: usize,
+    },
+    UnexpectedDottedKeys,
+    RepeatedKey {
+        key: String,
+    },
+    DuplicateKey {
+        key: String,
+        first: Range<usize>,
+        second: Range<usize>,
+    },
+    NotEncodedBytes(BytesEncoding),
+    ValueBeforeKey,
+    WriteFailed,
+    Io(String),
+    IndentTooSmall {
+        lineno: usize,
+    },
+    UnmatchedUnindent {
+        lineno: usize,
+    },
+}
+
+/// A component of the path to the location of an [`Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// The value of a map entry or struct field with the given key.
+    Key(String),
+    /// The element of a sequence or tuple with the given index.
+    Index(usize),
+}
+
+impl Error {
+    /// Returns the kind of error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Consumes the error, returning its kind.
+    pub fn into_kind(self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the byte range of the input at which the error occurred, if
+    /// known.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    /// Returns the path from the root of the input to the item at which the
+    /// error occurred, e.g. `[Key("solver"), Key("tolerances"), Index(2)]`.
+    pub fn path(&self) -> &[PathSegment] {
+        &self.path
+    }
+
+    /// Renders the error as a diagnostic for the given input.
+    ///
+    /// The diagnostic shows the line of `input` containing the error, with the
+    /// offending part underlined by carets and annotated with the error
+    /// message, and every protected context (`{...}`) it is nested in
+    /// underlined by dashes. The `input` should be the string that was passed
+    /// to the deserializer. Errors without a span, or with a span that does not
+    /// fit `input`, are rendered as a single line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[derive(serde::Deserialize, Debug)]
+    /// struct Solver {
+    ///     restart: u32,
+    /// }
+    ///
+    /// #[derive(serde::Deserialize, Debug)]
+    /// struct Config {
+    ///     solver: Solver,
+    /// }
+    ///
+    /// let s = "solver={restart=x}";
+    /// let error = stringly::from_str::<Config>(s).unwrap_err();
+    /// assert_eq!(
+    ///     error.render(s),
+    ///     "\
+    /// error: expected an unsigned integer
+    ///  --> line 1, column 17, at `solver.restart`
+    ///   |
+    /// 1 | solver={restart=x}
+    ///   |                 ^ expected an unsigned integer
+    ///   |        ----------- in protected context at level 1
+    /// "
+    /// );
+    /// ```
+    pub fn render(&self, input: &str) -> String {
+        let mut out = String::new();
+        let _ = self.write_diagnostic(&mut out, input);
+        out
+    }
+
+    fn write_diagnostic(&self, out: &mut String, input: &str) -> fmt::Result {
+        use fmt::Write;
+
+        writeln!(out, "error: {}", self.kind)?;
+        let span = match self.span {
+            Some(ref span)
+                if span.start <= span.end
+                    && span.end <= input.len()
+                    && input.is_char_boundary(span.start)
+                    && input.is_char_boundary(span.end) =>
+            {
+                span.clone()
+            }
+            _ => return Ok(()),
+        };
+
+        // Locate the line that contains the start of the span.
+        let line_start = input[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[span.start..]
+            .find('\n')
+            .map_or(input.len(), |i| span.start + i);
+        let lineno = input[..span.start].matches('\n').count() + 1;
+        let column = |i: usize| input[line_start..i].chars().count();
+
+        // Find the protected contexts that enclose the span, innermost first.
+        // The braces of a balancer directly inside a context, as in `{<{>a}}`,
+        // are matched like any other brace but do not delimit a context.
+        let mut contexts = Vec::new();
+        let mut opens = Vec::new();
+        let mut balancer = 0..0;
+        for (i, ch) in input.bytes().enumerate() {
+            if ch == b'<' && i >= balancer.end {
+                if let Some(n) = util::balancer_len(&input[i..]) {
+                    let bytes = input.as_bytes();
+                    if (i > 0 && bytes[i - 1] == b'{') || bytes.get(i + n) == Some(&b'}') {
+                        balancer = i..i + n;
+                    }
+                }
+            }
+            if ch == b'{' {
+                opens.push((i, balancer.contains(&i)));
+            } else if ch == b'}' {
+                if let Some((open, in_balancer)) = opens.pop() {
+                    if !in_balancer && !balancer.contains(&i) && open < span.start && i >= span.end
+                    {
+                        contexts.push(open..i + 1);
+                    }
+                }
+            }
+        }
+        contexts.sort_by_key(|context| core::cmp::Reverse(context.start));
+
+        let gutter = " ".repeat(lineno.to_string().len());
+        write!(
+            out,
+            "{}--> line {}, column {}",
+            gutter,
+            lineno,
+            column(span.start) + 1
+        )?;
+        if !self.path.is_empty() {
+            out.push_str(", at `");
+            self.write_path(out)?;
+            out.push('`');
+        }
+        writeln!(out)?;
+        writeln!(out, "{} |", gutter)?;
+        writeln!(out, "{} | {}", lineno, &input[line_start..line_end])?;
+        let underline = |out: &mut String, start: usize, end: usize, ch: char| {
+            let start = column(start.clamp(line_start, line_end));
+            let end = column(end.clamp(line_start, line_end));
+            write!(
+                out,
+                "{} | {}{}",
+                gutter,
+                " ".repeat(start),
+                ch.to_string().repeat((end - start).max(1))
+            )
+        };
+        underline(out, span.start, span.end, '^')?;
+        writeln!(out, " {}", self.kind)?;
+        for (i, context) in contexts.iter().enumerate() {
+            underline(out, context.start, context.end, '-')?;
+            writeln!(out, " in protected context at level {}", contexts.len() - i)?;
+        }
+        Ok(())
+    }
+
+    // Sets the span if it is not already set.
+    pub(crate) fn at(mut self, span: Range<usize>) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    fn write_path<W: fmt::Write>(&self, f: &mut W) -> fmt::Result {
+        for (i, segment) in self.path.iter().enumerate() {
+            match segment {
+                PathSegment::Key(key) if i == 0 => f.write_str(key)?,
+                PathSegment::Key(key) => write!(f, ".{}", key)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Maps the span, if any, through `f`.
+    pub(crate) fn map_span(mut self, f: impl Fn(usize) -> usize) -> Self {
+        self.span = self.span.map(|span| f(span.start)..f(span.end));
+        self
+    }
+
+    // Adds `segment` to the path. While the error propagates through the
+    // deserializer, the path is built innermost first, in constant time per
+    // segment, and reversed by `rooted` once the error leaves it.
+    pub(crate) fn within(mut self, segment: PathSegment) -> Self {
+        self.path.push(segment);
+        self
+    }
+
+    // Reverses the path built by `within` to start at the root.
+    pub(crate) fn rooted(mut self) -> Self {
+        self.path.reverse();
+        self
+    }
+}
+
+impl convert::From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            span: None,
+            path: Vec::new(),
+        }
+    }
 }
 
 impl ser::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        ErrorKind::Message(msg.to_string()).into()
     }
 }
 
 impl de::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        ErrorKind::Message(msg.to_string()).into()
     }
 }
 
 impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)?;
+        if !self.path.is_empty() {
+            f.write_str(" at `")?;
+            self.write_path(f)?;
+            f.write_str("`")?;
+        }
+        if let Some(ref span) = self.span {
+            write!(f, " (bytes {}..{})", span.start, span.end)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::Message(ref msg) => f.write_str(msg),
-            Error::NotABoolean => {
+            ErrorKind::Message(ref msg) => f.write_str(msg),
+            ErrorKind::NotABoolean => {
                 f.write_str("expected a boolean (`true`, `yes`, `false`, `no`; case insensitive)")
             }
-            Error::NotAnInteger => f.write_str("expected an integer"),
-            Error::NotAnUnsignedInteger => f.write_str("expected an unsigned integer"),
-            Error::NotAFloatingPointNumber => f.write_str("expected a floating point number"),
-            Error::NotASingleCharacter => f.write_str("expected a single character"),
-            Error::NotAnEnum => f.write_str("expected an enum (`VARIANT` or `VARIANT{ARGS}`"),
-            Error::NotAKeyValuePair => f.write_str("expected a key-value pair (`KEY=VALUE`)"),
-            Error::UnexpectedValueForUnit => f.write_str("unit got an unexpected value"),
-            Error::TooManyElements => f.write_str("too many elements"),
-            Error::IndentTooSmall { lineno } => write!(
+            ErrorKind::NotAnInteger => f.write_str("expected an integer"),
+            ErrorKind::NotAnUnsignedInteger => f.write_str("expected an unsigned integer"),
+            ErrorKind::IntegerOutOfRange { ty, min, max } => write!(
+                f,
+                "integer out of range for type `{}` ({}..={})",
+                ty, min, max
+            ),
+            ErrorKind::NegativeUnsignedInteger { ty } => {
+                write!(f, "expected a nonnegative integer for type `{}`", ty)
+            }
+            ErrorKind::NotAFloatingPointNumber => f.write_str("expected a floating point number"),
+            ErrorKind::NotASingleCharacter => f.write_str("expected a single character"),
+            ErrorKind::NotAnEnum => f.write_str("expected an enum (`VARIANT` or `VARIANT{ARGS}`"),
+            ErrorKind::NotAKeyValuePair => f.write_str("expected a key-value pair (`KEY=VALUE`)"),
+            ErrorKind::UnexpectedValueForUnit => f.write_str("unit got an unexpected value"),
+            ErrorKind::TooManyElements => f.write_str("too many elements"),
+            ErrorKind::NestedTooDeeply => f.write_str("nested too deeply"),
+            ErrorKind::PositionalAndNamedField { field } => {
+                write!(f, "field `{}` given both by position and by key", field)
+            }
+            ErrorKind::NotAnIndex { expected } => write!(f, "expected index `{}`", expected),
+            ErrorKind::UnexpectedDottedKeys => {
+                f.write_str("expected a value instead of dotted keys (`KEY.REST=VALUE`)")
+            }
+            ErrorKind::RepeatedKey { ref key } => {
+                write!(
+                    f,
+                    "key `{}` repeated for a value that is not a sequence",
+                    key
+                )
+            }
+            ErrorKind::DuplicateKey {
+                ref key, ref first, ..
+            } => write!(
+                f,
+                "duplicate key `{}`, first given at bytes {}..{}",
+                key, first.start, first.end
+            ),
+            ErrorKind::NotEncodedBytes(encoding) => {
+                write!(f, "expected {} encoded bytes", encoding)
+            }
+            ErrorKind::ValueBeforeKey => f.write_str("value requested before key"),
+            ErrorKind::WriteFailed => f.write_str("failed to write the output"),
+            ErrorKind::Io(ref msg) => write!(f, "I/O error: {}", msg),
+            ErrorKind::IndentTooSmall { lineno } => write!(
                 f,
                 "line {}: indentation should be two or more space but got one",
                 lineno
             ),
-            Error::UnmatchedUnindent { lineno } => write!(
+            ErrorKind::UnmatchedUnindent { lineno } => write!(
                 f,
                 "line {}: unindent does not match any outer indentation level",
                 lineno
@@ -75,7 +384,7 @@ impl std::error::Error for Error {}
 impl convert::From<SplitArgError> for Error {
     fn from(error: SplitArgError) -> Self {
         match error {
-            SplitArgError::NotAnEnum => Error::NotAnEnum,
+            SplitArgError::NotAnEnum => ErrorKind::NotAnEnum.into(),
         }
     }
 }
@@ -0,0 +1,361 @@
+//! Arithmetic expressions for numeric values.
+//!
+//! The grammar, in order of increasing precedence:
+//!
+//! ```text
+//! sum     = product (("+" | "-") product)*
+//! product = unary (("*" | "/") unary)*
+//! unary   = ("+" | "-") unary | power
+//! power   = atom ("**" unary)?
+//! atom    = number | "pi" | "e" | "(" sum ")"
+//! ```
+//!
+//! Exponentiation is right associative and binds more tightly than a unary
+//! minus on its left, as in Python: `-2**2` is `-4`. Spaces between tokens
+//! are ignored. Expressions nested deeper than `MAX_DEPTH` are invalid.
+
+use crate::de::extended_integer;
+
+#[cfg(not(feature = "std"))]
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::ops::Range;
+
+// Why an expression could not be evaluated, and the span of the offending
+// token relative to the start of the expression.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Failure {
+    // A syntax error, an unknown constant or a literal or operation that has
+    // no value of the number type, e.g. a division by zero for integers.
+    Invalid(Range<usize>),
+    // An integer operation overflowed.
+    Overflow(Range<usize>),
+}
+
+// The numbers an expression can evaluate to.
+trait Number: Copy {
+    fn literal(s: &str, extended: bool) -> Option<Self>;
+    fn constant(name: &str) -> Option<Self>;
+    // Applies the binary operator `op`, with `^` standing for `**`, or fails
+    // with the span of the operator.
+    fn apply(op: u8, a: Self, b: Self, span: Range<usize>) -> Result<Self, Failure>;
+    fn neg(self, span: Range<usize>) -> Result<Self, Failure>;
+}
+
+impl Number for f64 {
+    fn literal(s: &str, _extended: bool) -> Option<Self> {
+        s.parse().ok()
+    }
+
+    fn constant(name: &str) -> Option<Self> {
+        match name {
+            "pi" => Some(core::f64::consts::PI),
+            "e" => Some(core::f64::consts::E),
+            _ => None,
+        }
+    }
+
+    fn apply(op: u8, a: Self, b: Self, _span: Range<usize>) -> Result<Self, Failure> {
+        Ok(match op {
+            b'+' => a + b,
+            b'-' => a - b,
+            b'*' => a * b,
+            b'/' => a / b,
+            _ => a.powf(b),
+        })
+    }
+
+    fn neg(self, _span: Range<usize>) -> Result<Self, Failure> {
+        Ok(-self)
+    }
+}
+
+impl Number for i128 {
+    fn literal(s: &str, extended: bool) -> Option<Self> {
+        if extended {
+            let (digits, radix) = extended_integer(s)?;
+            i128::from_str_radix(&digits, radix).ok()
+        } else if s.bytes().all(|ch| ch.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    fn constant(_name: &str) -> Option<Self> {
+        None
+    }
+
+    fn apply(op: u8, a: Self, b: Self, span: Range<usize>) -> Result<Self, Failure> {
+        let v = match op {
+            b'+' => a.checked_add(b),
+            b'-' => a.checked_sub(b),
+            b'*' => a.checked_mul(b),
+            b'/' if b == 0 => return Err(Failure::Invalid(span)),
+            b'/' => a.checked_div(b),
+            _ if b < 0 => return Err(Failure::Invalid(span)),
+            _ => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        };
+        v.ok_or(Failure::Overflow(span))
+    }
+
+    fn neg(self, span: Range<usize>) -> Result<Self, Failure> {
+        self.checked_neg().ok_or(Failure::Overflow(span))
+    }
+}
+
+// The maximum nesting depth of parentheses, unary operators and exponents,
+// which bounds the recursion of the parser.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'s> {
+    s: &'s str,
+    pos: usize,
+    extended: bool,
+    depth: usize,
+}
+
+// Evaluates a floating point expression.
+pub(crate) fn eval_float(s: &str) -> Result<f64, Failure> {
+    Parser::new(s, false).eval()
+}
+
+// Evaluates an integer expression. Division truncates toward zero and
+// exponents must be nonnegative. With `extended`, literals are extended
+// integer literals as accepted by `Deserializer::extended_integers`.
+pub(crate) fn eval_integer(s: &str, extended: bool) -> Result<i128, Failure> {
+    Parser::new(s, extended).eval()
+}
+
+impl<'s> Parser<'s> {
+    fn new(s: &'s str, extended: bool) -> Self {
+        Parser {
+            s,
+            pos: 0,
+            extended,
+            depth: 0,
+        }
+    }
+
+    fn eval<N: Number>(mut self) -> Result<N, Failure> {
+        let v = self.sum()?;
+        self.skip_spaces();
+        if self.pos < self.s.len() {
+            return Err(Failure::Invalid(self.next_char_span()));
+        }
+        Ok(v)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.s[self.pos..].starts_with(' ') {
+            self.pos += 1;
+        }
+    }
+
+    // Skips spaces and consumes `token`, if present.
+    fn eat(&mut self, token: &str) -> Option<Range<usize>> {
+        self.skip_spaces();
+        let start = self.pos;
+        if self.s[start..].starts_with(token) {
+            self.pos += token.len();
+            Some(start..self.pos)
+        } else {
+            None
+        }
+    }
+
+    // The span of the next character, or the empty span at the end.
+    fn next_char_span(&self) -> Range<usize> {
+        let len = self.s[self.pos..].chars().next().map_or(0, char::len_utf8);
+        self.pos..self.pos + len
+    }
+
+    fn sum<N: Number>(&mut self) -> Result<N, Failure> {
+        let mut v = self.product()?;
+        loop {
+            let op = if let Some(span) = self.eat("+") {
+                (b'+', span)
+            } else if let Some(span) = self.eat("-") {
+                (b'-', span)
+            } else {
+                return Ok(v);
+            };
+            let rhs = self.product()?;
+            v = N::apply(op.0, v, rhs, op.1)?;
+        }
+    }
+
+    fn product<N: Number>(&mut self) -> Result<N, Failure> {
+        let mut v = self.unary()?;
+        loop {
+            self.skip_spaces();
+            let op = if self.s[self.pos..].starts_with("**") {
+                return Ok(v);
+            } else if let Some(span) = self.eat("*") {
+                (b'*', span)
+            } else if let Some(span) = self.eat("/") {
+                (b'/', span)
+            } else {
+                return Ok(v);
+            };
+            let rhs = self.unary()?;
+            v = N::apply(op.0, v, rhs, op.1)?;
+        }
+    }
+
+    fn unary<N: Number>(&mut self) -> Result<N, Failure> {
+        if self.depth > MAX_DEPTH {
+            self.skip_spaces();
+            return Err(Failure::Invalid(self.next_char_span()));
+        }
+        self.depth += 1;
+        let v = self.unary_nested();
+        self.depth -= 1;
+        v
+    }
+
+    fn unary_nested<N: Number>(&mut self) -> Result<N, Failure> {
+        if let Some(span) = self.eat("-") {
+            let v: N = self.unary()?;
+            v.neg(span)
+        } else if self.eat("+").is_some() {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power<N: Number>(&mut self) -> Result<N, Failure> {
+        let v = self.atom()?;
+        match self.eat("**") {
+            Some(span) => {
+                let exp = self.unary()?;
+                N::apply(b'^', v, exp, span)
+            }
+            None => Ok(v),
+        }
+    }
+
+    fn atom<N: Number>(&mut self) -> Result<N, Failure> {
+        if let Some(open) = self.eat("(") {
+            let v = self.sum()?;
+            return match self.eat(")") {
+                Some(_) => Ok(v),
+                None if self.pos == self.s.len() => Err(Failure::Invalid(open)),
+                None => Err(Failure::Invalid(self.next_char_span())),
+            };
+        }
+        let start = self.pos;
+        let bytes = self.s.as_bytes();
+        let mut end = start;
+        match bytes.get(start) {
+            Some(b'0'..=b'9' | b'.') => {
+                // A number, possibly with a fractional part and an exponent,
+                // or an extended integer literal.
+                let hex = matches!(bytes.get(start..start + 2), Some(b"0x" | b"0X"));
+                while end < bytes.len()
+                    && (bytes[end].is_ascii_alphanumeric() || b"._".contains(&bytes[end]))
+                {
+                    let exponent = !hex && matches!(bytes[end], b'e' | b'E');
+                    end += 1;
+                    if exponent && matches!(bytes.get(end), Some(b'+' | b'-')) {
+                        end += 1;
+                    }
+                }
+                let token = &self.s[start..end];
+                self.pos = end;
+                N::literal(token, self.extended).ok_or(Failure::Invalid(start..end))
+            }
+            Some(ch) if ch.is_ascii_alphabetic() => {
+                while end < bytes.len() && bytes[end].is_ascii_alphanumeric() {
+                    end += 1;
+                }
+                self.pos = end;
+                N::constant(&self.s[start..end]).ok_or(Failure::Invalid(start..end))
+            }
+            _ => Err(Failure::Invalid(self.next_char_span())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_float() {
+        for (s, v) in [
+            ("1/64", 0.015625),
+            ("2*pi", 2.0 * core::f64::consts::PI),
+            ("e", core::f64::consts::E),
+            ("2**10", 1024.0),
+            ("2**3**2", 512.0),
+            ("-2**2", -4.0),
+            ("2**-1", 0.5),
+            ("1 + 2 * 3", 7.0),
+            ("(1 + 2) * 3", 9.0),
+            ("1-2-3", -4.0),
+            ("8/4/2", 1.0),
+            ("1e-3*2", 0.002),
+            ("1.5E+2-50", 100.0),
+            (".5", 0.5),
+            ("--1", 1.0),
+            ("1/0", f64::INFINITY),
+        ] {
+            assert_eq!(eval_float(s), Ok(v), "{}", s);
+        }
+        for (s, span) in [
+            ("", 0..0),
+            ("1+", 2..2),
+            ("1 2", 2..3),
+            ("2pi", 0..3),
+            ("tau", 0..3),
+            ("(1+2", 0..1),
+            ("(1+2]", 4..5),
+            ("1)", 1..2),
+            ("1e", 0..2),
+            ("1é", 1..3),
+            ("1***2", 3..4),
+        ] {
+            assert_eq!(eval_float(s), Err(Failure::Invalid(span)), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_integer() {
+        for (s, v) in [
+            ("2**10", 1024),
+            ("7/2", 3),
+            ("-7/2", -3),
+            ("(1+2)*3-4", 5),
+            ("-2**2", -4),
+        ] {
+            assert_eq!(eval_integer(s, false), Ok(v), "{}", s);
+        }
+        assert_eq!(eval_integer("0xff+1_000", true), Ok(1255));
+        assert_eq!(eval_integer("0xe-1", true), Ok(13));
+        assert_eq!(eval_integer("0xff", false), Err(Failure::Invalid(0..4)));
+        assert_eq!(eval_integer("1.5*2", false), Err(Failure::Invalid(0..3)));
+        assert_eq!(eval_integer("2*pi", false), Err(Failure::Invalid(2..4)));
+        assert_eq!(eval_integer("1/0", false), Err(Failure::Invalid(1..2)));
+        assert_eq!(eval_integer("2**-1", false), Err(Failure::Invalid(1..3)));
+        assert_eq!(eval_integer("2**200", false), Err(Failure::Overflow(1..3)));
+        assert_eq!(
+            eval_integer("2**126*2", false),
+            Err(Failure::Overflow(6..7))
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |n| ["(".repeat(n), "1".to_string(), ")".repeat(n)].concat();
+        assert_eq!(eval_float(&nested(MAX_DEPTH)), Ok(1.0));
+        assert_eq!(
+            eval_float(&nested(MAX_DEPTH + 1)),
+            Err(Failure::Invalid(MAX_DEPTH + 1..MAX_DEPTH + 2))
+        );
+        assert!(eval_float(&nested(200000)).is_err());
+        assert!(eval_integer(&"-".repeat(200000), false).is_err());
+        assert!(eval_integer(&"2**".repeat(200000), false).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Text formats of floating point numbers.
+
+#[cfg(not(feature = "std"))]
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fmt;
+
+/// The text format of floating point numbers.
+///
+/// The format is selected with [`Serializer::float_format`] and defaults to
+/// [`Shortest`](FloatFormat::Shortest). Regardless of the format, NaN is
+/// serialized as `NaN` and the infinities as `inf` and `-inf`, which, like
+/// every format below, are accepted by the deserializer.
+///
+/// [`Serializer::float_format`]: crate::Serializer::float_format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FloatFormat {
+    /// The shortest digits that round-trip, in positional notation if the
+    /// decimal exponent lies in `-4..16` and in scientific notation
+    /// otherwise: `0.001`, `1e-12`, `1.5e20`.
+    #[default]
+    Shortest,
+    /// The shortest digits that round-trip, in positional notation if the
+    /// decimal exponent lies in `lower..upper` and in scientific notation
+    /// otherwise. With `lower == upper` all numbers are in scientific
+    /// notation.
+    Thresholds { lower: i32, upper: i32 },
+    /// The given number of significant digits, without trailing zeros, in
+    /// the notation of [`Shortest`](FloatFormat::Shortest).
+    Significant(usize),
+    /// Positional notation with the given number of digits after the decimal
+    /// point.
+    Fixed(usize),
+}
+
+// The floating point types.
+pub(crate) trait Float: Copy + fmt::Display + fmt::LowerExp {
+    fn is_finite(self) -> bool;
+}
+
+impl Float for f32 {
+    fn is_finite(self) -> bool {
+        f32::is_finite(self)
+    }
+}
+
+impl Float for f64 {
+    fn is_finite(self) -> bool {
+        f64::is_finite(self)
+    }
+}
+
+impl FloatFormat {
+    pub(crate) fn format<T: Float>(self, v: T) -> String {
+        if !v.is_finite() {
+            // `NaN`, `inf` or `-inf`.
+            return v.to_string();
+        }
+        let (sci, lower, upper) = match self {
+            FloatFormat::Shortest => (format!("{:e}", v), -4, 16),
+            FloatFormat::Thresholds { lower, upper } => (format!("{:e}", v), lower, upper),
+            FloatFormat::Significant(n) => (format!("{:.*e}", n.max(1) - 1, v), -4, 16),
+            FloatFormat::Fixed(n) => return format!("{:.*}", n, v),
+        };
+        // `sci` is of the form `[-]D[.DDD]eX`.
+        let (mantissa, exp) = sci.split_once('e').unwrap();
+        let exp: i32 = exp.parse().unwrap();
+        let (sign, mantissa) = match mantissa.strip_prefix('-') {
+            Some(mantissa) => ("-", mantissa),
+            None => ("", mantissa),
+        };
+        let digits: String = mantissa.chars().filter(|&ch| ch != '.').collect();
+        let digits = match digits.trim_end_matches('0') {
+            "" => "0",
+            digits => digits,
+        };
+        let mut s = String::from(sign);
+        if exp < lower || exp >= upper {
+            s.push_str(&digits[..1]);
+            if digits.len() > 1 {
+                s.push('.');
+                s.push_str(&digits[1..]);
+            }
+            s.push('e');
+            s.push_str(&exp.to_string());
+        } else if exp < 0 {
+            s.push_str("0.");
+            s.extend(core::iter::repeat('0').take((-exp - 1) as usize));
+            s.push_str(digits);
+        } else {
+            let int = exp as usize + 1;
+            if digits.len() <= int {
+                s.push_str(digits);
+                s.extend(core::iter::repeat('0').take(int - digits.len()));
+            } else {
+                s.push_str(&digits[..int]);
+                s.push('.');
+                s.push_str(&digits[int..]);
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shortest() {
+        let shortest = FloatFormat::Shortest;
+        for (v, s) in [
+            (0.0, "0"),
+            (-0.0, "-0"),
+            (1.0, "1"),
+            (1.2, "1.2"),
+            (-1.2, "-1.2"),
+            (123.456, "123.456"),
+            (1e15, "1000000000000000"),
+            (1.5e16, "1.5e16"),
+            (1e300, "1e300"),
+            (0.0001, "0.0001"),
+            (0.00012, "0.00012"),
+            (1e-5, "1e-5"),
+            (1e-12, "1e-12"),
+            (-2.5e-8, "-2.5e-8"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+        ] {
+            assert_eq!(shortest.format(v), s);
+            if v.is_nan() {
+                assert!(s.parse::<f64>().unwrap().is_nan());
+            } else {
+                assert_eq!(s.parse::<f64>().unwrap(), v);
+            }
+        }
+        assert_eq!(shortest.format(0.1f32), "0.1");
+        assert_eq!(shortest.format(1e-6f32), "1e-6");
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let sci = FloatFormat::Thresholds { lower: 0, upper: 0 };
+        assert_eq!(sci.format(1.0), "1e0");
+        assert_eq!(sci.format(1234.5), "1.2345e3");
+        assert_eq!(sci.format(0.0), "0e0");
+        let wide = FloatFormat::Thresholds {
+            lower: -12,
+            upper: 3,
+        };
+        assert_eq!(wide.format(1e-12), "0.000000000001");
+        assert_eq!(wide.format(999.0), "999");
+        assert_eq!(wide.format(1000.0), "1e3");
+    }
+
+    #[test]
+    fn test_significant() {
+        let three = FloatFormat::Significant(3);
+        assert_eq!(three.format(0.1 + 0.2), "0.3");
+        assert_eq!(three.format(123456.0), "123000");
+        assert_eq!(three.format(2.0 / 3.0), "0.667");
+        assert_eq!(three.format(1.23456e-8), "1.23e-8");
+        assert_eq!(three.format(9.999), "10");
+        assert_eq!(FloatFormat::Significant(0).format(0.25), "0.2");
+    }
+
+    #[test]
+    fn test_fixed() {
+        assert_eq!(FloatFormat::Fixed(2).format(1.0), "1.00");
+        assert_eq!(FloatFormat::Fixed(2).format(-0.125), "-0.12");
+        assert_eq!(FloatFormat::Fixed(0).format(2.5), "2");
+        assert_eq!(FloatFormat::Fixed(3).format(f64::NEG_INFINITY), "-inf");
+    }
+}
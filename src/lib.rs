@@ -21,7 +21,10 @@
 //!     ```
 //!
 //! *   Numbers are serialized by the usual string representation (like in
-//!     JSON).
+//!     JSON). Floating point numbers use the shortest digits that round-trip,
+//!     in scientific notation if very small or large, unless configured
+//!     otherwise with [`Serializer::float_format`]. NaN and the infinities
+//!     are serialized as `NaN`, `inf` and `-inf`.
 //!
 //!     ```
 //!     # macro_rules! check { ($v:expr => $s:literal) => { check(&$v, $s) } }
@@ -33,6 +36,10 @@
 //!     1i32 => "1"
 //!     # ); check!(
 //!     1.2f64 => "1.2"
+//!     # ); check!(
+//!     1e-12f64 => "1e-12"
+//!     # ); check!(
+//!     f64::INFINITY => "inf"
 //!     # );
 //!     ```
 //!
@@ -99,6 +106,131 @@
 //! nonnegative numbers such that the protected string does not have negative
 //! curly scopes.
 //!
+//! ## Multi-line form
+//!
+//! Large serializations can be expanded into an indented, multi-line form
+//! with one item per line, see [`util::prettify`], and collapsed again with
+//! [`util::deprettify`]. The functions [`to_string_pretty`] and
+//! [`from_str_pretty`] serialize into and deserialize from this form.
+//! [`util::prettify_width`] and [`to_string_pretty_width`] keep groups that
+//! fit in a given line width inline.
+//!
+//! ## Type inference
+//!
+//! Stringly is not self-describing: the meaning of a serialization depends on
+//! the type it is deserialized into. Serde features that buffer the input
+//! before the type is known, such as untagged enums, use a best-effort
+//! inference of the type from the text instead, which applies the first
+//! matching rule of the following list:
+//!
+//! 1.  An empty string is a unit (`()` or `None`).
+//! 2.  `true` and `false`, case insensitive, are booleans.
+//! 3.  A string that parses as an integer is an integer.
+//! 4.  A string that contains a digit and parses as a floating point number is
+//!     a floating point number.
+//! 5.  A string of which every (comma separated) item is a `KEY=VALUE` pair is
+//!     a map with string keys.
+//! 6.  A `VARIANT{VALUE}` is an enum, represented as a map with a single entry.
+//! 7.  A string with multiple items or a single protected item is a sequence.
+//! 8.  Anything else is a string.
+//!
+//! Values whose serialization matches an earlier rule than intended, e.g. the
+//! string `1` or a sequence with a single element, cannot be deserialized this
+//! way. Maps, enums and sequences nested more than 128 levels deep are not
+//! inferred but rejected.
+//!
+//! ```
+//! #[derive(serde::Deserialize, Debug, PartialEq)]
+//! #[serde(untagged)]
+//! enum Tol {
+//!     Value(f64),
+//!     Preset(String),
+//! }
+//!
+//! assert_eq!(stringly::from_str::<Tol>("1e-6").unwrap(), Tol::Value(1e-6));
+//! assert_eq!(stringly::from_str::<Tol>("auto").unwrap(), Tol::Preset("auto".to_string()));
+//! ```
+//!
+//! The same holds for the entries of structs with `#[serde(flatten)]` fields:
+//! the values of all entries that do not belong to regular fields are
+//! inferred. A flattened map catches all remaining entries. By default,
+//! booleans and numbers are inferred as such: a map of [`Value`]s receives
+//! them written back to text, e.g. `true` becomes `True` and `1.10` becomes
+//! `1.1`, and a `HashMap<String, String>` rejects them. With
+//! [`Deserializer::verbatim_scalars`] enabled, both receive the verbatim text,
+//! at the expense of flattened boolean and number fields.
+//!
+//! ```
+//! #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+//! struct SolverOpts {
+//!     tol: f64,
+//! }
+//!
+//! #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+//! struct Run {
+//!     nelems: u32,
+//!     #[serde(flatten)]
+//!     solver: SolverOpts,
+//! }
+//!
+//! let run = Run { nelems: 10, solver: SolverOpts { tol: 0.5 } };
+//! assert_eq!(stringly::to_string(&run).unwrap(), "nelems=10,tol=0.5");
+//! assert_eq!(stringly::from_str::<Run>("tol=0.5,nelems=10").unwrap(), run);
+//!
+//! #[derive(serde::Deserialize, Debug)]
+//! struct Extra<'a> {
+//!     nelems: u32,
+//!     #[serde(flatten, borrow)]
+//!     rest: std::collections::BTreeMap<String, stringly::Value<'a>>,
+//! }
+//!
+//! let extra = stringly::from_str::<Extra>("nelems=10,degree=2,basis=spline").unwrap();
+//! assert_eq!(extra.rest["degree"], "2");
+//! assert_eq!(extra.rest["basis"], "spline");
+//!
+//! #[derive(serde::Deserialize, Debug)]
+//! struct Text {
+//!     nelems: u32,
+//!     #[serde(flatten)]
+//!     rest: std::collections::BTreeMap<String, String>,
+//! }
+//!
+//! let de = stringly::Deserializer::from_str("nelems=10,degree=2,lazy=true");
+//! let text: Text = serde::Deserialize::deserialize(de.verbatim_scalars(true)).unwrap();
+//! assert_eq!((text.rest["degree"].as_str(), text.rest["lazy"].as_str()), ("2", "true"));
+//! ```
+//!
+//! Internally tagged enums, `#[serde(tag = "...")]`, are serialized as a map
+//! with the discriminator as the first entry and are deserialized via
+//! inference as well. Consequently, every field of a variant must hold text
+//! that is inferred as the field's type: a `String` field holding `1` or
+//! `true` is rejected with an invalid type error, unless
+//! [`Deserializer::verbatim_scalars`] is enabled, as shown below. Fields of
+//! type [`Value`] accept any text.
+//!
+//! ```
+//! #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+//! #[serde(tag = "type", rename_all = "lowercase")]
+//! enum Solver {
+//!     Direct,
+//!     Gmres { restart: u32, tol: f64 },
+//! }
+//!
+//! let solver = Solver::Gmres { restart: 30, tol: 0.5 };
+//! assert_eq!(stringly::to_string(&solver).unwrap(), "type=gmres,restart=30,tol=0.5");
+//! assert_eq!(stringly::from_str::<Solver>("type=gmres,restart=30,tol=0.5").unwrap(), solver);
+//!
+//! #[derive(serde::Deserialize, Debug, PartialEq)]
+//! #[serde(tag = "type", rename_all = "lowercase")]
+//! enum Precon {
+//!     Ilu { name: String },
+//! }
+//!
+//! let de = stringly::Deserializer::from_str("type=ilu,name=1").verbatim_scalars(true);
+//! let precon: Precon = serde::Deserialize::deserialize(de).unwrap();
+//! assert_eq!(precon, Precon::Ilu { name: "1".to_string() });
+//! ```
+//!
 //! # Examples
 //!
 //! ```
@@ -116,16 +248,30 @@
 //! assert_eq!(stringly::from_str::<Example>(&s).unwrap(), v);
 //! ```
 
+#[cfg(not(feature = "std"))]
+extern crate alloc;
 extern crate serde;
 
+mod bytes;
 mod de;
 mod error;
+mod expr;
+mod float;
+mod raw;
 mod ser;
 pub mod util;
+pub mod value;
+pub mod with;
 
-pub use de::{from_str, Deserializer};
-pub use error::{Error, Result};
-pub use ser::{to_string, Serializer};
+pub use bytes::BytesEncoding;
+pub use de::{from_str, from_str_pretty, Deserializer};
+pub use error::{Error, ErrorKind, PathSegment, Result};
+pub use float::FloatFormat;
+pub use raw::RawValue;
+#[cfg(feature = "std")]
+pub use ser::to_writer;
+pub use ser::{to_fmt, to_string, to_string_pretty, to_string_pretty_width, Serializer};
+pub use value::{from_value, to_value, Value};
 
 #[cfg(test)]
 mod tests;
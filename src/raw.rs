@@ -0,0 +1,210 @@
+//! Deferred deserialization.
+
+use serde::de::{self, Deserialize, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+#[cfg(not(feature = "std"))]
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fmt;
+
+/// A substring of Stringly data whose deserialization is deferred.
+///
+/// A `&RawValue` or `Box<RawValue>` can be used as the type of a struct field
+/// to capture the (unprotected) text of that field, as it would have been
+/// passed to the field's deserializer. The text can be deserialized later
+/// with [`from_str`], for example once the type is known from a sibling
+/// field. When serialized, the text is emitted verbatim, subject to the usual
+/// protection rules.
+///
+/// [`from_str`]: crate::from_str
+///
+/// # Examples
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use stringly::RawValue;
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct Plugin<'a> {
+///     kind: &'a str,
+///     #[serde(borrow)]
+///     config: &'a RawValue,
+/// }
+///
+/// let s = "kind=resize,config={width=3,height=4}";
+/// let plugin: Plugin = stringly::from_str(s).unwrap();
+/// assert_eq!(plugin.config.get(), "width=3,height=4");
+/// assert_eq!(stringly::to_string(&plugin).unwrap(), s);
+///
+/// #[derive(Deserialize)]
+/// struct Resize {
+///     width: u32,
+///     height: u32,
+/// }
+///
+/// let resize: Resize = stringly::from_str(plugin.config.get()).unwrap();
+/// assert_eq!((resize.width, resize.height), (3, 4));
+/// ```
+#[repr(transparent)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RawValue {
+    text: str,
+}
+
+impl RawValue {
+    /// Wraps text in a `&RawValue`.
+    pub fn new(text: &str) -> &RawValue {
+        // SAFETY: `RawValue` is a transparent wrapper around `str`.
+        unsafe { &*(text as *const str as *const RawValue) }
+    }
+
+    /// Wraps text in a `Box<RawValue>`.
+    pub fn from_string(text: String) -> Box<RawValue> {
+        let text = Box::into_raw(text.into_boxed_str());
+        // SAFETY: `RawValue` is a transparent wrapper around `str`.
+        unsafe { Box::from_raw(text as *mut RawValue) }
+    }
+
+    /// Returns the captured text.
+    pub fn get(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Clone for Box<RawValue> {
+    fn clone(&self) -> Self {
+        (**self).to_owned()
+    }
+}
+
+impl ToOwned for RawValue {
+    type Owned = Box<RawValue>;
+
+    fn to_owned(&self) -> Self::Owned {
+        RawValue::from_string(self.text.to_owned())
+    }
+}
+
+impl fmt::Debug for RawValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RawValue").field(&&self.text).finish()
+    }
+}
+
+impl fmt::Display for RawValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Serialize for RawValue {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for &'a RawValue {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct BorrowedVisitor;
+
+        impl<'de> Visitor<'de> for BorrowedVisitor {
+            type Value = &'de RawValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a borrowed string")
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'de str) -> core::result::Result<Self::Value, E> {
+                Ok(RawValue::new(v))
+            }
+        }
+
+        deserializer.deserialize_str(BorrowedVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Box<RawValue> {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct OwnedVisitor;
+
+        impl<'de> Visitor<'de> for OwnedVisitor {
+            type Value = Box<RawValue>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_str<E>(self, v: &str) -> core::result::Result<Self::Value, E> {
+                Ok(RawValue::from_string(v.to_owned()))
+            }
+
+            fn visit_string<E>(self, v: String) -> core::result::Result<Self::Value, E> {
+                Ok(RawValue::from_string(v))
+            }
+        }
+
+        deserializer.deserialize_string(OwnedVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_str, to_string};
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn test_deferred() {
+        #[derive(Deserialize, Serialize)]
+        struct Section {
+            kind: String,
+            args: Box<RawValue>,
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum Solver {
+            Gmres { restart: u32, tol: f64 },
+            Direct,
+        }
+
+        let s = "kind=solver,args=Gmres{restart=30,tol=0.1}";
+        let section: Section = from_str(s).unwrap();
+        assert_eq!(section.args.get(), "Gmres{restart=30,tol=0.1}");
+        assert_eq!(
+            from_str::<Solver>(section.args.get()).unwrap(),
+            Solver::Gmres {
+                restart: 30,
+                tol: 0.1
+            }
+        );
+        assert_eq!(to_string(&section).unwrap(), s);
+
+        let section: Section = from_str("kind=solver,args=Direct").unwrap();
+        assert_eq!(
+            from_str::<Solver>(section.args.get()).unwrap(),
+            Solver::Direct
+        );
+    }
+
+    #[test]
+    fn test_protection() {
+        assert_eq!(to_string(&RawValue::new("a,b")).unwrap(), "a,b");
+        assert_eq!(
+            to_string(&vec![RawValue::new("a,b"), RawValue::new("}")]).unwrap(),
+            "{a,b},{<{>}}"
+        );
+        let v: Vec<&RawValue> = from_str("{a,b},{<{>}}").unwrap();
+        assert_eq!(v, [RawValue::new("a,b"), RawValue::new("}")]);
+        assert_eq!(v[1].to_owned(), RawValue::from_string("}".to_string()));
+    }
+}
@@ -1,117 +1,603 @@
 use serde::ser::{self, Serialize};
 
-use crate::error::{Error, Result};
+use crate::bytes::BytesEncoding;
+use crate::error::{Error, ErrorKind, Result};
+use crate::float::{Float, FloatFormat};
 use crate::util;
 
-pub struct Serializer;
-pub struct SerializeSequence {
+#[cfg(not(feature = "std"))]
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{fmt, io};
+
+/// A serializer that writes Stringly into a [`fmt::Write`], by default a
+/// `String`.
+///
+/// This replaces the unit struct `Serializer` of earlier versions, which
+/// returned the serialization of every value as a `String`. Code that
+/// serialized with `value.serialize(Serializer)` should call [`to_string`]
+/// instead, or serialize into `&mut Serializer::new(String::new())` and take
+/// the output with [`into_inner`](Self::into_inner):
+///
+/// ```
+/// use serde::Serialize;
+///
+/// let mut ser = stringly::Serializer::new(String::new());
+/// (1, "a").serialize(&mut ser).unwrap();
+/// assert_eq!(ser.into_inner(), "1,a");
+/// ```
+///
+/// Whether an item needs [protection][crate#protection] depends on the
+/// serialization of the item as a whole, which is not known before the item
+/// is written. Rather than building the serialization of every item in a
+/// separate string, every direct child of the serialized object is
+/// serialized twice: once to determine the protection of the child and all
+/// its descendants and once to write the output, applying the protection
+/// decided before. Consequently, the [`Serialize`] implementations of the
+/// serialized objects must be deterministic.
+pub struct Serializer<W = String> {
+    output: W,
+    // Protection of items, in the order in which the items are serialized,
+    // and the index of the next protection to apply.
+    protections: Vec<Protection>,
+    next: usize,
+    // Summaries of the items being planned, innermost last. Output is
+    // discarded while planning.
+    plans: Vec<Summary>,
+    bytes: BytesEncoding,
+    floats: FloatFormat,
+    bare_flags: bool,
+    positional: usize,
+    dotted_keys: bool,
+    repeated_keys: bool,
+}
+
+pub struct SerializeSequence<'a, W> {
+    ser: &'a mut Serializer<W>,
     n: usize,
-    output: String,
+    // The number of leading fields serialized by position.
+    positional: usize,
 }
-pub struct SerializeVariantSequence {
-    variant: &'static str,
+
+pub struct SerializeVariantSequence<'a, W> {
+    ser: &'a mut Serializer<W>,
     n: usize,
-    output: String,
+    positional: usize,
+    body: Body,
+}
+
+impl<W: fmt::Write> Serializer<W> {
+    /// Creates a serializer that writes into `output`.
+    pub fn new(output: W) -> Self {
+        Serializer {
+            output,
+            protections: Vec::new(),
+            next: 0,
+            plans: Vec::new(),
+            bytes: BytesEncoding::default(),
+            floats: FloatFormat::default(),
+            bare_flags: false,
+            positional: 0,
+            dotted_keys: false,
+            repeated_keys: false,
+        }
+    }
+
+    /// Sets the text encoding of byte arrays.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Serializer as _;
+    /// use stringly::{BytesEncoding, Serializer};
+    ///
+    /// let mut ser = Serializer::new(String::new()).bytes_encoding(BytesEncoding::Base64);
+    /// ser.serialize_bytes(b"foo").unwrap();
+    /// assert_eq!(ser.into_inner(), "Zm9v");
+    /// ```
+    pub fn bytes_encoding(mut self, encoding: BytesEncoding) -> Self {
+        self.bytes = encoding;
+        self
+    }
+
+    /// Sets the text format of floating point numbers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Serialize;
+    /// use stringly::{FloatFormat, Serializer};
+    ///
+    /// let mut ser = Serializer::new(String::new()).float_format(FloatFormat::Significant(3));
+    /// (2.0f64 / 3.0, 1e-12f64).serialize(&mut ser).unwrap();
+    /// assert_eq!(ser.into_inner(), "0.667,1e-12");
+    /// ```
+    pub fn float_format(mut self, format: FloatFormat) -> Self {
+        self.floats = format;
+        self
+    }
+
+    /// Enables bare flags for struct fields.
+    ///
+    /// A field whose value is `true`, `Some(())` or `()` is serialized as its
+    /// bare key, as accepted by [`Deserializer::bare_flags`]. Disabled by
+    /// default.
+    ///
+    /// [`Deserializer::bare_flags`]: crate::Deserializer::bare_flags
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Args {
+    ///     verbose: bool,
+    ///     cache: bool,
+    ///     tol: f64,
+    /// }
+    ///
+    /// let args = Args { verbose: true, cache: false, tol: 1e-6 };
+    /// let mut ser = stringly::Serializer::new(String::new()).bare_flags(true);
+    /// args.serialize(&mut ser).unwrap();
+    /// assert_eq!(ser.into_inner(), "verbose,cache=False,tol=1e-6");
+    /// ```
+    pub fn bare_flags(mut self, enabled: bool) -> Self {
+        self.bare_flags = enabled;
+        self
+    }
+
+    /// Serializes the first `n` fields of structs and struct variants by
+    /// position, without key, as accepted by
+    /// [`Deserializer::positional_fields`]. Fields following a skipped field
+    /// are serialized with key. Defaults to zero.
+    ///
+    /// [`Deserializer::positional_fields`]: crate::Deserializer::positional_fields
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// enum Solver {
+    ///     Gmres { restart: u32, tol: f64 },
+    /// }
+    ///
+    /// let mut ser = stringly::Serializer::new(String::new()).positional_fields(1);
+    /// Solver::Gmres { restart: 30, tol: 1e-8 }.serialize(&mut ser).unwrap();
+    /// assert_eq!(ser.into_inner(), "Gmres{30,tol=1e-8}");
+    /// ```
+    pub fn positional_fields(mut self, n: usize) -> Self {
+        self.positional = n;
+        self
+    }
+
+    /// Serializes the fields of structs nested in struct fields as dotted keys
+    /// `FIELD.NESTED=VALUE`, as accepted by [`Deserializer::dotted_keys`].
+    /// Structs without fields, struct variants and the items of sequences and
+    /// maps are serialized as usual, except that keys containing a `.` are
+    /// protected, as in `{a.b}=1`. Disabled by default.
+    ///
+    /// [`Deserializer::dotted_keys`]: crate::Deserializer::dotted_keys
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Solver {
+    ///     tol: f64,
+    ///     maxiter: u32,
+    /// }
+    ///
+    /// #[derive(Serialize)]
+    /// struct Config {
+    ///     solver: Solver,
+    ///     nelems: Vec<u32>,
+    /// }
+    ///
+    /// let config = Config {
+    ///     solver: Solver { tol: 1e-6, maxiter: 100 },
+    ///     nelems: vec![4, 8],
+    /// };
+    /// let mut ser = stringly::Serializer::new(String::new()).dotted_keys(true);
+    /// config.serialize(&mut ser).unwrap();
+    /// assert_eq!(ser.into_inner(), "solver.tol=1e-6,solver.maxiter=100,nelems={4,8}");
+    /// ```
+    pub fn dotted_keys(mut self, enabled: bool) -> Self {
+        self.dotted_keys = enabled;
+        self
+    }
+
+    /// Serializes struct fields whose value is a sequence of at least two
+    /// elements as repeated keys `FIELD=ELEMENT`, one per element, as
+    /// accepted by [`Deserializer::repeated_keys`]. Disabled by default.
+    ///
+    /// [`Deserializer::repeated_keys`]: crate::Deserializer::repeated_keys
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Args {
+    ///     input: Vec<&'static str>,
+    ///     output: &'static str,
+    /// }
+    ///
+    /// let args = Args { input: vec!["a.msh", "b.msh"], output: "c.msh" };
+    /// let mut ser = stringly::Serializer::new(String::new()).repeated_keys(true);
+    /// args.serialize(&mut ser).unwrap();
+    /// assert_eq!(ser.into_inner(), "input=a.msh,input=b.msh,output=c.msh");
+    /// ```
+    pub fn repeated_keys(mut self, enabled: bool) -> Self {
+        self.repeated_keys = enabled;
+        self
+    }
+
+    /// Unwraps the output.
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    // The rule for keys, which protects dots if dotted keys are enabled, so
+    // that the key is not taken for a dotted key.
+    fn key_rule(&self) -> Rule {
+        if self.dotted_keys {
+            Rule::DottedKey
+        } else {
+            Rule::Key
+        }
+    }
+
+    // Protects a field name like an item with the rule for keys.
+    fn protect_key(&self, key: &str) -> String {
+        if self.dotted_keys {
+            util::protect(key, [',', '=', '.'])
+        } else {
+            util::protect(key, [',', '='])
+        }
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<()> {
+        match self.plans.last_mut() {
+            Some(plan) => {
+                plan.push_str(s);
+                Ok(())
+            }
+            None => self
+                .output
+                .write_str(s)
+                .map_err(|_| ErrorKind::WriteFailed.into()),
+        }
+    }
+
+    fn write_display<T: fmt::Display>(&mut self, v: T) -> Result<()> {
+        fmt::Write::write_fmt(&mut Output(self), format_args!("{}", v))
+            .map_err(|_| ErrorKind::WriteFailed.into())
+    }
+
+    fn write_float<T: Float>(&mut self, v: T) -> Result<()> {
+        let s = self.floats.format(v);
+        self.write_str(&s)
+    }
+
+    fn write_prefix(&mut self, protection: Protection) -> Result<()> {
+        if let Protection::Protect { l, left, .. } = protection {
+            self.write_str("{")?;
+            if left {
+                self.write_str("<")?;
+                for _i in 0..l {
+                    self.write_str("{")?;
+                }
+                self.write_str(">")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_suffix(&mut self, protection: Protection) -> Result<()> {
+        if let Protection::Protect { r, right, .. } = protection {
+            if right {
+                self.write_str("<")?;
+                for _i in 0..r {
+                    self.write_str("}")?;
+                }
+                self.write_str(">")?;
+            }
+            self.write_str("}")?;
+        }
+        Ok(())
+    }
+
+    // Serializes `value` as an item of the current item, protected according
+    // to `rule`.
+    fn item<T>(&mut self, value: &T, rule: Rule) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if !self.plans.is_empty() {
+            let i = self.protections.len();
+            self.protections.push(Protection::None);
+            self.plans.push(Summary::default());
+            value.serialize(&mut *self)?;
+            self.end_plan(i, rule);
+        } else {
+            if self.next == self.protections.len() {
+                self.plan(value, rule)?;
+            }
+            let protection = self.protections[self.next];
+            self.next += 1;
+            self.write_prefix(protection)?;
+            value.serialize(&mut *self)?;
+            self.write_suffix(protection)?;
+            if self.next == self.protections.len() {
+                self.protections.clear();
+                self.next = 0;
+            }
+        }
+        Ok(())
+    }
+
+    // Determines the protection of `value` and all its items, without writing
+    // output, and returns the summary of the protected serialization.
+    fn plan<T>(&mut self, value: &T, rule: Rule) -> Result<Summary>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.plans.push(Summary::default());
+        let result = self.item(value, rule);
+        let summary = self.plans.pop().unwrap_or_default();
+        if result.is_err() {
+            self.plans.clear();
+        }
+        result.map(|()| summary)
+    }
+
+    // Completes the plan of the item with protection index `i` and adds the
+    // protected item to the enclosing plan.
+    fn end_plan(&mut self, i: usize, rule: Rule) {
+        let summary = self.plans.pop().unwrap_or_default();
+        let protection = summary.protection(rule);
+        self.protections[i] = protection;
+        if let Some(plan) = self.plans.last_mut() {
+            plan.append(&summary.protect(protection));
+        }
+    }
+
+    fn begin_body(&mut self) -> Result<Body> {
+        if !self.plans.is_empty() {
+            let i = self.protections.len();
+            self.protections.push(Protection::None);
+            self.plans.push(Summary::default());
+            Ok(Body::Planned(i))
+        } else if self.next < self.protections.len() {
+            let protection = self.protections[self.next];
+            self.next += 1;
+            self.write_prefix(protection)?;
+            Ok(Body::Replayed(protection))
+        } else {
+            Ok(Body::Streamed {
+                open: false,
+                ends_with_balancer: false,
+            })
+        }
+    }
+}
+
+// Adapter for writing formatted values.
+struct Output<'a, W>(&'a mut Serializer<W>);
+
+impl<W: fmt::Write> fmt::Write for Output<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s).map_err(|_| fmt::Error)
+    }
 }
 
 /// Serializes an object to Stringly.
 pub fn to_string<T>(value: &T) -> Result<String>
 where
-    T: Serialize,
+    T: ?Sized + Serialize,
+{
+    let mut output = String::new();
+    to_fmt(&mut output, value)?;
+    Ok(output)
+}
+
+/// Serializes an object to the multi-line form of Stringly.
+///
+/// See [`util::prettify`] for a description of the multi-line form.
+///
+/// # Examples
+///
+/// ```
+/// let s = stringly::to_string_pretty(&[(1, 2), (3, 4)]).unwrap();
+/// assert_eq!(s, "-\n  1\n  2\n-\n  3\n  4");
+/// ```
+pub fn to_string_pretty<T>(value: &T) -> Result<String>
+where
+    T: ?Sized + Serialize,
+{
+    to_string(value).map(|output| util::prettify(&output))
+}
+
+/// Serializes an object to the multi-line form of Stringly, keeping groups
+/// inline where they fit in the given line width.
+///
+/// See [`util::prettify_width`] for a description of the layout.
+///
+/// # Examples
+///
+/// ```
+/// let s = stringly::to_string_pretty_width(&[(1, 2), (3, 4)], 6).unwrap();
+/// assert_eq!(s, "{1,2}\n{3,4}");
+/// ```
+pub fn to_string_pretty_width<T>(value: &T, width: usize) -> Result<String>
+where
+    T: ?Sized + Serialize,
 {
-    value.serialize(Serializer)
+    to_string(value).map(|output| util::prettify_width(&output, width))
+}
+
+/// Serializes an object to Stringly into a [`fmt::Write`].
+///
+/// # Examples
+///
+/// ```
+/// let mut s = String::from("args: ");
+/// stringly::to_fmt(&mut s, &(1, "a,b")).unwrap();
+/// assert_eq!(s, "args: 1,{a,b}");
+/// ```
+pub fn to_fmt<W, T>(output: W, value: &T) -> Result<()>
+where
+    W: fmt::Write,
+    T: ?Sized + Serialize,
+{
+    value.serialize(&mut Serializer::new(output))
+}
+
+/// Serializes an object to Stringly into an [`io::Write`].
+///
+/// # Examples
+///
+/// ```
+/// let mut v = Vec::new();
+/// stringly::to_writer(&mut v, &(1, "a,b")).unwrap();
+/// assert_eq!(v, b"1,{a,b}");
+/// ```
+#[cfg(feature = "std")]
+pub fn to_writer<W, T>(output: W, value: &T) -> Result<()>
+where
+    W: io::Write,
+    T: ?Sized + Serialize,
+{
+    let mut output = IoOutput {
+        output,
+        error: None,
+    };
+    match to_fmt(&mut output, value) {
+        Err(error) if *error.kind() == ErrorKind::WriteFailed => match output.error {
+            Some(error) => Err(ErrorKind::Io(error.to_string()).into()),
+            None => Err(error),
+        },
+        result => result,
+    }
+}
+
+// Adapter from `io::Write` to `fmt::Write` that retains the I/O error.
+#[cfg(feature = "std")]
+struct IoOutput<W> {
+    output: W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> fmt::Write for IoOutput<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.output.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
 }
 
-impl ser::Serializer for Serializer {
-    type Ok = String;
+impl<'a, W: fmt::Write> ser::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
     type Error = Error;
 
-    type SerializeSeq = SerializeSequence;
-    type SerializeTuple = SerializeSequence;
-    type SerializeTupleStruct = SerializeSequence;
-    type SerializeTupleVariant = SerializeVariantSequence;
-    type SerializeMap = SerializeSequence;
-    type SerializeStruct = SerializeSequence;
-    type SerializeStructVariant = SerializeVariantSequence;
+    type SerializeSeq = SerializeSequence<'a, W>;
+    type SerializeTuple = SerializeSequence<'a, W>;
+    type SerializeTupleStruct = SerializeSequence<'a, W>;
+    type SerializeTupleVariant = SerializeVariantSequence<'a, W>;
+    type SerializeMap = SerializeSequence<'a, W>;
+    type SerializeStruct = SerializeSequence<'a, W>;
+    type SerializeStructVariant = SerializeVariantSequence<'a, W>;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
-        Ok((if v { "True" } else { "False" }).to_string())
+        self.write_str(if v { "True" } else { "False" })
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        self.write_display(v)
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_display(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        self.write_display(v)
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_float(v)
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_float(v)
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_str(v.encode_utf8(&mut [0; 4]))
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        Ok(v.to_string())
+        self.write_str(v)
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok> {
-        unimplemented! {}
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
+        let encoded = self.bytes.encode(v);
+        self.serialize_str(&encoded)
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
-        Ok("".to_string())
+        Ok(())
     }
 
     fn serialize_some<T>(self, value: &T) -> Result<Self::Ok>
     where
         T: ?Sized + Serialize,
     {
-        let s = value.serialize(self)?;
-        if s.starts_with('{') && s.ends_with('}') || s.is_empty() {
-            Ok(util::protect_unconditionally(&s))
-        } else {
-            Ok(s)
-        }
+        self.item(value, Rule::Some)
     }
 
     fn serialize_unit(self) -> Result<Self::Ok> {
-        Ok("".to_string())
+        Ok(())
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
-        Ok("".to_string())
+        Ok(())
     }
 
     fn serialize_unit_variant(
@@ -120,14 +606,13 @@ impl ser::Serializer for Serializer {
         _variant_index: u32,
         variant: &'static str,
     ) -> Result<Self::Ok> {
-        Ok(variant.to_string())
+        self.write_str(variant)
     }
 
     fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Self::Ok>
     where
         T: ?Sized + Serialize,
     {
-        // TODO: check this
         value.serialize(self)
     }
 
@@ -141,26 +626,23 @@ impl ser::Serializer for Serializer {
     where
         T: ?Sized + Serialize,
     {
-        // TODO: assert '{' and '}' not in `variant`
-        let value = value.serialize(self)?;
-        if value.is_empty() {
-            Ok(variant.to_string())
-        } else {
-            Ok([variant.to_string(), util::protect_unconditionally(&value)].concat())
-        }
+        self.write_str(variant)?;
+        self.item(value, Rule::Argument)
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
         Ok(SerializeSequence {
+            ser: self,
             n: 0,
-            output: String::new(),
+            positional: 0,
         })
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
         Ok(SerializeSequence {
+            ser: self,
             n: 0,
-            output: String::new(),
+            positional: 0,
         })
     }
 
@@ -170,8 +652,9 @@ impl ser::Serializer for Serializer {
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
         Ok(SerializeSequence {
+            ser: self,
             n: 0,
-            output: String::new(),
+            positional: 0,
         })
     }
 
@@ -182,25 +665,30 @@ impl ser::Serializer for Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        // TODO: assert '{' and '}' not in `variant`
+        self.write_str(variant)?;
+        let body = self.begin_body()?;
         Ok(SerializeVariantSequence {
-            variant,
+            ser: self,
             n: 0,
-            output: String::new(),
+            positional: 0,
+            body,
         })
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
         Ok(SerializeSequence {
+            ser: self,
             n: 0,
-            output: String::new(),
+            positional: 0,
         })
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        let positional = self.positional;
         Ok(SerializeSequence {
+            ser: self,
             n: 0,
-            output: String::new(),
+            positional,
         })
     }
 
@@ -211,188 +699,974 @@ impl ser::Serializer for Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        // TODO: assert '{' and '}' not in `variant`
+        self.write_str(variant)?;
+        let body = self.begin_body()?;
+        let positional = self.positional;
         Ok(SerializeVariantSequence {
-            variant,
+            ser: self,
             n: 0,
-            output: String::new(),
+            positional,
+            body,
         })
     }
 }
 
-fn protect_comma_or_empty(s: &str) -> String {
-    if s.is_empty() {
-        "{}".to_string()
-    } else {
-        util::protect(s, ',')
+impl<W: fmt::Write> SerializeSequence<'_, W> {
+    fn element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.n != 0 {
+            self.ser.write_str(",")?;
+        }
+        self.n += 1;
+        self.ser.item(value, Rule::Element)
+    }
+
+    fn key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.n != 0 {
+            self.ser.write_str(",")?;
+        }
+        self.n += 1;
+        let rule = self.ser.key_rule();
+        self.ser.item(key, rule)?;
+        self.ser.write_str("=")
+    }
+
+    // Writes a key that is protected already, such as a dotted key.
+    fn protected_key(&mut self, key: &str) -> Result<()> {
+        if self.n != 0 {
+            self.ser.write_str(",")?;
+        }
+        self.n += 1;
+        self.ser.write_str(key)?;
+        self.ser.write_str("=")
+    }
+
+    fn field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.n < self.positional {
+            // Protected for `=` like a key, to not be taken for a key.
+            if self.n != 0 {
+                self.ser.write_str(",")?;
+            }
+            self.n += 1;
+            self.ser.item(value, Rule::Key)
+        } else if self.ser.bare_flags && shape(value) == Shape::Flag {
+            if self.n != 0 {
+                self.ser.write_str(",")?;
+            }
+            self.n += 1;
+            let rule = self.ser.key_rule();
+            self.ser.item(key, rule)
+        } else {
+            self.named_field(None, key, value)
+        }
+    }
+
+    // Serializes a field as `KEY=VALUE`, or spread over several items as
+    // dotted keys or repeated keys, if enabled. The key is prefixed by the
+    // protected `path` of the structs spread as dotted keys, if any.
+    fn named_field<T>(&mut self, path: Option<&str>, key: &str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let spread = match shape(value) {
+            Shape::Struct => self.ser.dotted_keys,
+            Shape::Sequence => self.ser.repeated_keys,
+            _ => false,
+        };
+        if !spread && path.is_none() {
+            self.key(key)?;
+            return self.ser.item(value, Rule::Value);
+        }
+        let key = match path {
+            Some(path) => format!("{}.{}", path, self.ser.protect_key(key)),
+            None => self.ser.protect_key(key),
+        };
+        if spread {
+            value.serialize(Spread { seq: self, key })
+        } else {
+            self.protected_key(&key)?;
+            self.ser.item(value, Rule::Value)
+        }
     }
 }
 
-impl ser::SerializeSeq for SerializeSequence {
-    type Ok = String;
+impl<W: fmt::Write> ser::SerializeSeq for SerializeSequence<'_, W> {
+    type Ok = ();
     type Error = Error;
 
     fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        if self.n != 0 {
-            self.output += ",";
-        }
-        self.n += 1;
-        self.output += &protect_comma_or_empty(&value.serialize(Serializer)?);
-        Ok(())
+        self.element(value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(self.output)
+        Ok(())
     }
 }
 
-impl ser::SerializeTuple for SerializeSequence {
-    type Ok = String;
+impl<W: fmt::Write> ser::SerializeTuple for SerializeSequence<'_, W> {
+    type Ok = ();
     type Error = Error;
 
     fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        if self.n != 0 {
-            self.output += ",";
-        }
-        self.n += 1;
-        self.output += &protect_comma_or_empty(&value.serialize(Serializer)?);
-        Ok(())
+        self.element(value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(self.output)
+        Ok(())
     }
 }
 
-impl ser::SerializeTupleStruct for SerializeSequence {
-    type Ok = String;
+impl<W: fmt::Write> ser::SerializeTupleStruct for SerializeSequence<'_, W> {
+    type Ok = ();
     type Error = Error;
 
     fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        if self.n != 0 {
-            self.output += ",";
-        }
-        self.n += 1;
-        self.output += &protect_comma_or_empty(&value.serialize(Serializer)?);
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
         Ok(())
     }
+}
+
+impl<W: fmt::Write> ser::SerializeMap for SerializeSequence<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.key(key)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.ser.item(value, Rule::Value)
+    }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(self.output)
+        Ok(())
     }
 }
 
-impl ser::SerializeTupleVariant for SerializeVariantSequence {
-    type Ok = String;
+impl<W: fmt::Write> ser::SerializeStruct for SerializeSequence<'_, W> {
+    type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
+        self.field(key, value)
+    }
+
+    fn skip_field(&mut self, _key: &'static str) -> Result<()> {
+        self.positional = self.positional.min(self.n);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(())
+    }
+}
+
+// State of the unconditionally protected body of a tuple or struct variant.
+enum Body {
+    // The body is being planned; the protection is stored at this index.
+    Planned(usize),
+    // The body is written with this, previously planned, protection.
+    Replayed(Protection),
+    // The body is written without a plan. Since the body is balanced, only the
+    // balancers depend on the items of the body, which are planned one by one.
+    Streamed {
+        open: bool,
+        ends_with_balancer: bool,
+    },
+}
+
+impl<W: fmt::Write> SerializeVariantSequence<'_, W> {
+    // Serializes an item of the body: either a value or a key-value pair.
+    fn item<K, V>(&mut self, key: Option<&K>, value: &V) -> Result<()>
+    where
+        K: ?Sized + Serialize,
+        V: ?Sized + Serialize,
+    {
+        let positional = key.is_some() && self.n < self.positional;
+        let key = key.filter(|_| !positional);
+        let value_rule = if positional {
+            // Protected for `=` like a key, to not be taken for a key.
+            Rule::Key
+        } else if key.is_some() {
+            Rule::Value
+        } else {
+            Rule::Element
+        };
+        let flag = key.is_some() && self.ser.bare_flags && shape(value) == Shape::Flag;
+        if let Body::Streamed {
+            ref mut open,
+            ref mut ends_with_balancer,
+        } = self.body
+        {
+            let mut summary = Summary::default();
+            if let Some(key) = key {
+                let rule = self.ser.key_rule();
+                summary = self.ser.plan(key, rule)?;
+                if !flag {
+                    summary.push_str("=");
+                }
+            }
+            if !flag {
+                summary.append(&self.ser.plan(value, value_rule)?);
+            }
+            if !*open {
+                self.ser.write_str("{")?;
+                if summary.starts_with_balancer() {
+                    self.ser.write_str("<>")?;
+                }
+                *open = true;
+            }
+            *ends_with_balancer = summary.ends_with_balancer();
+        }
         if self.n != 0 {
-            self.output += ",";
+            self.ser.write_str(",")?;
         }
         self.n += 1;
-        self.output += &protect_comma_or_empty(&value.serialize(Serializer)?);
-        Ok(())
+        if let Some(key) = key {
+            let rule = self.ser.key_rule();
+            self.ser.item(key, rule)?;
+            if flag {
+                return Ok(());
+            }
+            self.ser.write_str("=")?;
+        }
+        self.ser.item(value, value_rule)
+    }
+
+    fn end_body(self) -> Result<()> {
+        match self.body {
+            Body::Planned(i) => {
+                self.ser.end_plan(i, Rule::Body);
+                Ok(())
+            }
+            Body::Replayed(protection) => self.ser.write_suffix(protection),
+            Body::Streamed {
+                open,
+                ends_with_balancer,
+            } => {
+                if !open {
+                    self.ser.write_str("{")?;
+                }
+                if ends_with_balancer {
+                    self.ser.write_str("<>")?;
+                }
+                self.ser.write_str("}")
+            }
+        }
+    }
+}
+
+impl<W: fmt::Write> ser::SerializeTupleVariant for SerializeVariantSequence<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.item(None::<&str>, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok([
-            self.variant.to_string(),
-            util::protect_unconditionally(&self.output),
-        ]
-        .concat())
+        self.end_body()
     }
 }
 
-impl ser::SerializeMap for SerializeSequence {
-    type Ok = String;
+impl<W: fmt::Write> ser::SerializeStructVariant for SerializeVariantSequence<'_, W> {
+    type Ok = ();
     type Error = Error;
 
-    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        if self.n != 0 {
-            self.output += ",";
-        }
-        self.n += 1;
-        self.output += &util::protect(&key.serialize(Serializer)?, [',', '=']);
-        self.output += "=";
+        self.item(Some(key), value)
+    }
+
+    fn skip_field(&mut self, _key: &'static str) -> Result<()> {
+        self.positional = self.positional.min(self.n);
         Ok(())
     }
 
-    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    fn end(self) -> Result<Self::Ok> {
+        self.end_body()
+    }
+}
+
+// The value of the field `key`, spread over several items of `seq`: the
+// fields of a struct as items `KEY.FIELD=VALUE`, recursively, and the
+// elements of a sequence as items `KEY=ELEMENT`.
+struct Spread<'a, 'b, W> {
+    seq: &'a mut SerializeSequence<'b, W>,
+    key: String,
+}
+
+macro_rules! not_spread {
+    ($($method:ident($($ty:ty),*))*) => {
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<()> {
+                Err(ErrorKind::Message("expected a struct or a sequence".to_string()).into())
+            }
+        )*
+    };
+}
+
+impl<'a, 'b, W: fmt::Write> ser::Serializer for Spread<'a, 'b, W> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = ser::Impossible<(), Error>;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_some<T>(self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        self.output += &util::protect(&value.serialize(Serializer)?, ',');
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(ErrorKind::Message("expected a struct or a sequence".to_string()).into())
+    }
+
+    not_spread! {
+        serialize_bool(bool)
+        serialize_i8(i8)
+        serialize_i16(i16)
+        serialize_i32(i32)
+        serialize_i64(i64)
+        serialize_i128(i128)
+        serialize_u8(u8)
+        serialize_u16(u16)
+        serialize_u32(u32)
+        serialize_u64(u64)
+        serialize_u128(u128)
+        serialize_f32(f32)
+        serialize_f64(f64)
+        serialize_char(char)
+        serialize_str(&str)
+        serialize_bytes(&[u8])
+        serialize_none()
+        serialize_unit()
+        serialize_unit_struct(&'static str)
+        serialize_unit_variant(&'static str, u32, &'static str)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(ErrorKind::Message("expected a struct or a sequence".to_string()).into())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(ErrorKind::Message("expected a struct or a sequence".to_string()).into())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(ErrorKind::Message("expected a struct or a sequence".to_string()).into())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(ErrorKind::Message("expected a struct or a sequence".to_string()).into())
+    }
+}
+
+impl<W: fmt::Write> Spread<'_, '_, W> {
+    fn element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.seq.protected_key(&self.key)?;
+        self.seq.ser.item(value, Rule::Value)
+    }
+}
+
+impl<W: fmt::Write> ser::SerializeSeq for Spread<'_, '_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
         Ok(())
     }
+}
 
-    fn end(self) -> Result<Self::Ok> {
-        Ok(self.output)
+impl<W: fmt::Write> ser::SerializeTuple for Spread<'_, '_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
     }
 }
 
-impl ser::SerializeStruct for SerializeSequence {
-    type Ok = String;
+impl<W: fmt::Write> ser::SerializeStruct for Spread<'_, '_, W> {
+    type Ok = ();
     type Error = Error;
 
     fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        if self.n != 0 {
-            self.output += ",";
-        }
+        self.seq.named_field(Some(&self.key), key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// The shapes of values that some fields are serialized differently for.
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    // A flag that is set: `true`, `Some(())` or `()`.
+    Flag,
+    // A struct with at least one (serialized) field.
+    Struct,
+    // A sequence or tuple of at least two elements.
+    Sequence,
+    Other,
+}
+
+// Returns the shape of `value`, looking through options and newtype structs.
+fn shape<T>(value: &T) -> Shape
+where
+    T: ?Sized + Serialize,
+{
+    value.serialize(ShapeProbe).unwrap_or(Shape::Other)
+}
+
+// A serializer that tells the shape of a value, failing for compound values
+// of other shapes.
+struct ShapeProbe;
+
+// The fields or elements of a compound value seen by `ShapeProbe`, and the
+// shape of the value if it has at least `min` of them.
+struct CompoundProbe {
+    n: usize,
+    min: usize,
+    shape: Shape,
+}
+
+impl CompoundProbe {
+    fn shape(self) -> Result<Shape> {
+        Ok(if self.n >= self.min {
+            self.shape
+        } else {
+            Shape::Other
+        })
+    }
+}
+
+macro_rules! other_shape {
+    ($($method:ident($($ty:ty),*))*) => {
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<Shape> {
+                Ok(Shape::Other)
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for ShapeProbe {
+    type Ok = Shape;
+    type Error = Error;
+
+    type SerializeSeq = CompoundProbe;
+    type SerializeTuple = CompoundProbe;
+    type SerializeTupleStruct = ser::Impossible<Shape, Error>;
+    type SerializeTupleVariant = ser::Impossible<Shape, Error>;
+    type SerializeMap = ser::Impossible<Shape, Error>;
+    type SerializeStruct = CompoundProbe;
+    type SerializeStructVariant = ser::Impossible<Shape, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Shape> {
+        Ok(if v { Shape::Flag } else { Shape::Other })
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Shape>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Shape> {
+        Ok(Shape::Flag)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Shape>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Shape>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Shape::Other)
+    }
+
+    other_shape! {
+        serialize_i8(i8)
+        serialize_i16(i16)
+        serialize_i32(i32)
+        serialize_i64(i64)
+        serialize_i128(i128)
+        serialize_u8(u8)
+        serialize_u16(u16)
+        serialize_u32(u32)
+        serialize_u64(u64)
+        serialize_u128(u128)
+        serialize_f32(f32)
+        serialize_f64(f64)
+        serialize_char(char)
+        serialize_str(&str)
+        serialize_bytes(&[u8])
+        serialize_none()
+        serialize_unit_struct(&'static str)
+        serialize_unit_variant(&'static str, u32, &'static str)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(CompoundProbe {
+            n: 0,
+            min: 2,
+            shape: Shape::Sequence,
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(CompoundProbe {
+            n: 0,
+            min: 2,
+            shape: Shape::Sequence,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(ErrorKind::Message(String::new()).into())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(ErrorKind::Message(String::new()).into())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(ErrorKind::Message(String::new()).into())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(CompoundProbe {
+            n: 0,
+            min: 1,
+            shape: Shape::Struct,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(ErrorKind::Message(String::new()).into())
+    }
+}
+
+impl ser::SerializeSeq for CompoundProbe {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
         self.n += 1;
-        self.output += &util::protect(&key.serialize(Serializer)?, [',', '=']);
-        self.output += "=";
-        self.output += &util::protect(&value.serialize(Serializer)?, ',');
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok> {
-        Ok(self.output)
+    fn end(self) -> Result<Shape> {
+        self.shape()
     }
 }
 
-impl ser::SerializeStructVariant for SerializeVariantSequence {
-    type Ok = String;
+impl ser::SerializeTuple for CompoundProbe {
+    type Ok = Shape;
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, _value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        if self.n != 0 {
-            self.output += ",";
-        }
         self.n += 1;
-        self.output += &util::protect(&key.serialize(Serializer)?, [',', '=']);
-        self.output += "=";
-        self.output += &util::protect(&value.serialize(Serializer)?, ',');
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok> {
-        Ok([
-            self.variant.to_string(),
-            util::protect_unconditionally(&self.output),
-        ]
-        .concat())
+    fn end(self) -> Result<Shape> {
+        self.shape()
+    }
+}
+
+impl ser::SerializeStruct for CompoundProbe {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, _key: &'static str, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.n += 1;
+        Ok(())
+    }
+
+    fn end(self) -> Result<Shape> {
+        self.shape()
+    }
+}
+
+// Protection rules for items, see `Summary::protection`.
+#[derive(Clone, Copy)]
+enum Rule {
+    // Item of a sequence: protected for `,` or if empty.
+    Element,
+    // Key of a mapping: protected for `,` and `=`.
+    Key,
+    // Key of a mapping with dotted keys: protected for `,`, `=` and `.`.
+    DottedKey,
+    // Value of a mapping: protected for `,`.
+    Value,
+    // Value of an option: protected unconditionally if empty or enclosed in
+    // curly braces, otherwise never.
+    Some,
+    // Argument of a newtype variant: protected unconditionally unless empty.
+    Argument,
+    // Body of a tuple or struct variant: protected unconditionally.
+    Body,
+}
+
+/// Protection applied to an item, see [`util::protect`].
+///
+/// [`util::protect`]: crate::util::protect
+#[derive(Clone, Copy)]
+enum Protection {
+    None,
+    Protect {
+        l: usize,
+        r: usize,
+        left: bool,
+        right: bool,
+    },
+}
+
+// State of the automaton that detects a left balancer (`'<' '{'* '>'`) at the
+// start of a string.
+#[derive(Clone, Copy, PartialEq)]
+enum Head {
+    Start,
+    Open,
+    Yes,
+    No,
+}
+
+impl Head {
+    fn step(self, ch: char) -> Head {
+        match (self, ch) {
+            (Head::Start, '<') => Head::Open,
+            (Head::Open, '{') => Head::Open,
+            (Head::Open, '>') => Head::Yes,
+            (Head::Start, _) | (Head::Open, _) => Head::No,
+            (state, _) => state,
+        }
+    }
+}
+
+// State of the automaton that detects a right balancer (`'<' '}'* '>'`) at
+// the end of a string.
+#[derive(Clone, Copy, PartialEq)]
+enum Tail {
+    None,
+    Open,
+    Done,
+}
+
+impl Tail {
+    fn step(self, ch: char) -> Tail {
+        match (self, ch) {
+            (_, '<') => Tail::Open,
+            (Tail::Open, '}') => Tail::Open,
+            (Tail::Open, '>') => Tail::Done,
+            _ => Tail::None,
+        }
+    }
+}
+
+/// The properties of a string that determine its protection, see
+/// [`util::protect`]. Summaries of concatenated strings can be computed from
+/// the summaries of the parts.
+///
+/// [`util::protect`]: crate::util::protect
+#[derive(Clone, Copy)]
+struct Summary {
+    empty: bool,
+    starts_with_brace: bool,
+    ends_with_brace: bool,
+    // The curly brace level at the end and the minimum level.
+    level: isize,
+    min_level: isize,
+    // `true` if a `,`, `=` or `.` occurs at level zero.
+    comma: bool,
+    equals: bool,
+    dot: bool,
+    // The final states of the balancer automatons for every initial state.
+    head: [Head; 2],
+    tail: [Tail; 3],
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary {
+            empty: true,
+            starts_with_brace: false,
+            ends_with_brace: false,
+            level: 0,
+            min_level: 0,
+            comma: false,
+            equals: false,
+            dot: false,
+            head: [Head::Start, Head::Open],
+            tail: [Tail::None, Tail::Open, Tail::Done],
+        }
+    }
+}
+
+impl Summary {
+    fn push_str(&mut self, s: &str) {
+        for ch in s.chars() {
+            if self.empty {
+                self.empty = false;
+                self.starts_with_brace = ch == '{';
+            }
+            self.ends_with_brace = ch == '}';
+            match ch {
+                '{' => self.level += 1,
+                '}' => {
+                    self.level -= 1;
+                    self.min_level = self.min_level.min(self.level);
+                }
+                ',' if self.level == 0 => self.comma = true,
+                '=' if self.level == 0 => self.equals = true,
+                '.' if self.level == 0 => self.dot = true,
+                _ => {}
+            }
+            for state in self.head.iter_mut() {
+                *state = state.step(ch);
+            }
+            for state in self.tail.iter_mut() {
+                *state = state.step(ch);
+            }
+        }
+    }
+
+    // Appends the summary of a string. Occurrences of `,`, `=` and `.` in the
+    // appended string are only accounted for if the level of this string is
+    // zero, which is always the case for the balanced items joined by the
+    // serializer.
+    fn append(&mut self, other: &Summary) {
+        if other.empty {
+            return;
+        }
+        if self.empty {
+            *self = *other;
+            return;
+        }
+        self.ends_with_brace = other.ends_with_brace;
+        if self.level == 0 {
+            self.comma |= other.comma;
+            self.equals |= other.equals;
+            self.dot |= other.dot;
+        }
+        self.min_level = self.min_level.min(self.level + other.min_level);
+        self.level += other.level;
+        for state in self.head.iter_mut() {
+            *state = match *state {
+                Head::Start => other.head[0],
+                Head::Open => other.head[1],
+                state => state,
+            };
+        }
+        for state in self.tail.iter_mut() {
+            *state = other.tail[*state as usize];
+        }
+    }
+
+    fn starts_with_balancer(&self) -> bool {
+        self.head[0] == Head::Yes
+    }
+
+    fn ends_with_balancer(&self) -> bool {
+        self.tail[0] == Tail::Done
+    }
+
+    fn protection(&self, rule: Rule) -> Protection {
+        let braces = self.starts_with_brace && self.ends_with_brace;
+        let l = -self.min_level;
+        let r = self.level + l;
+        let protect = match rule {
+            Rule::Element => self.empty || braces || self.comma || l > 0 || r > 0,
+            Rule::Key => braces || self.comma || self.equals || l > 0 || r > 0,
+            Rule::DottedKey => braces || self.comma || self.equals || self.dot || l > 0 || r > 0,
+            Rule::Value => braces || self.comma || l > 0 || r > 0,
+            Rule::Some => self.empty || braces,
+            Rule::Argument => !self.empty,
+            Rule::Body => true,
+        };
+        if protect {
+            Protection::Protect {
+                l: l as usize,
+                r: r as usize,
+                left: l > 0 || self.starts_with_balancer(),
+                right: r > 0 || self.ends_with_balancer(),
+            }
+        } else {
+            Protection::None
+        }
+    }
+
+    // Returns the summary of the protected string.
+    fn protect(&self, protection: Protection) -> Summary {
+        match protection {
+            Protection::None => *self,
+            Protection::Protect { l, r, left, right } => {
+                let mut summary = Summary::default();
+                summary.push_str("{");
+                if left {
+                    summary.push_str("<");
+                    for _i in 0..l {
+                        summary.push_str("{");
+                    }
+                    summary.push_str(">");
+                }
+                summary.append(self);
+                if right {
+                    summary.push_str("<");
+                    for _i in 0..r {
+                        summary.push_str("}");
+                    }
+                    summary.push_str(">");
+                }
+                summary.push_str("}");
+                summary
+            }
+        }
     }
 }
 
@@ -443,4 +1717,104 @@ mod tests {
         let expected = r#"Struct{a=1}"#;
         assert_eq!(to_string(&s).unwrap(), expected);
     }
+
+    // Compares the serialization of items, constructed from every string up to
+    // length 4 composed of characters that are relevant for protection, with
+    // the serialization constructed with `util::protect`.
+    #[test]
+    fn test_protection() {
+        use crate::util::{protect, protect_unconditionally};
+
+        #[derive(Serialize)]
+        enum E<'a> {
+            Newtype(&'a str),
+            Tuple(&'a str, &'a str),
+            Struct { a: &'a str },
+        }
+
+        let element = |s: &str| {
+            if s.is_empty() {
+                "{}".to_string()
+            } else {
+                protect(s, ',')
+            }
+        };
+        let argument = |s: &str| {
+            if s.is_empty() {
+                String::new()
+            } else {
+                protect_unconditionally(s)
+            }
+        };
+        let chars = ['{', '}', '<', '>', ',', '=', '.', 'a'];
+        let mut s = String::new();
+        for length in 0..5u32 {
+            for i in 0..chars.len().pow(length) {
+                s.clear();
+                let mut k = i;
+                for _j in 0..length {
+                    s.push(chars[k % chars.len()]);
+                    k /= chars.len();
+                }
+                let s = s.as_str();
+                let pair = [element(s), element(s)].join(",");
+                let tuple = ["Tuple", &protect_unconditionally(&pair)].concat();
+                let some = if s.is_empty() || s.starts_with('{') && s.ends_with('}') {
+                    protect_unconditionally(s)
+                } else {
+                    s.to_string()
+                };
+                let newtype = ["Newtype", &argument(s)].concat();
+                let field = ["a=", &protect(s, ',')].concat();
+                let strct = ["Struct", &protect_unconditionally(&field)].concat();
+                let map = [&protect(s, [',', '=']), "=", &protect(s, ',')].concat();
+                assert_eq!(to_string(&[s, s]).unwrap(), pair);
+                assert_eq!(to_string(&[[s, s]]).unwrap(), element(&pair));
+                assert_eq!(to_string(&E::Tuple(s, s)).unwrap(), tuple);
+                assert_eq!(to_string(&[E::Tuple(s, s)]).unwrap(), element(&tuple));
+                assert_eq!(to_string(&Some(s)).unwrap(), some);
+                assert_eq!(to_string(&[Some(s)]).unwrap(), element(&some));
+                assert_eq!(to_string(&E::Newtype(s)).unwrap(), newtype);
+                assert_eq!(to_string(&[E::Newtype(s)]).unwrap(), element(&newtype));
+                assert_eq!(to_string(&E::Struct { a: s }).unwrap(), strct);
+                assert_eq!(to_string(&[E::Struct { a: s }]).unwrap(), element(&strct));
+                let pairs = [(s, s)]
+                    .iter()
+                    .cloned()
+                    .collect::<std::collections::BTreeMap<_, _>>();
+                assert_eq!(to_string(&pairs).unwrap(), map);
+                let dotted = [&protect(s, [',', '=', '.']), "=", &protect(s, ',')].concat();
+                let mut ser = Serializer::new(String::new()).dotted_keys(true);
+                pairs.serialize(&mut ser).unwrap();
+                assert_eq!(ser.into_inner(), dotted);
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_to_writer() {
+        #[derive(Serialize)]
+        struct Test {
+            int: u32,
+            seq: Vec<&'static str>,
+        }
+
+        let tests: Vec<_> = (0..3)
+            .map(|int| Test {
+                int,
+                seq: vec!["a", "b"],
+            })
+            .collect();
+        let expected = r#"{int=0,seq={a,b}},{int=1,seq={a,b}},{int=2,seq={a,b}}"#;
+        let mut output = Vec::new();
+        to_writer(&mut output, &tests).unwrap();
+        assert_eq!(output, expected.as_bytes());
+
+        let mut output = [0u8; 10];
+        assert!(matches!(
+            to_writer(&mut output[..], &tests),
+            Err(error) if matches!(error.kind(), ErrorKind::Io(_))
+        ));
+    }
 }
@@ -1,6 +1,6 @@
-use crate::de::from_str;
-use crate::error::{Error, Result};
-use crate::ser::to_string;
+use crate::de::{from_str, Deserializer};
+use crate::error::{Error, ErrorKind, Result};
+use crate::ser::{to_string, Serializer};
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
 
@@ -26,10 +26,32 @@ macro_rules! assert_de {
 
 macro_rules! assert_de_error {
     ($T:ty, $serial:literal, $err:expr) => {
-        assert_eq!(from_str($serial) as Result<$T>, Err($err));
+        assert_eq!(
+            (from_str($serial) as Result<$T>).map_err(Error::into_kind),
+            Err($err)
+        );
     };
 }
 
+// A `Deserializer` and `Serializer` configuration, e.g. to enable an option
+// for both.
+struct Configured {
+    de: for<'a> fn(Deserializer<&'a str>) -> Deserializer<&'a str>,
+    ser: fn(Serializer<String>) -> Serializer<String>,
+}
+
+impl Configured {
+    fn deserialize<'a, T: Deserialize<'a>>(&self, serial: &'a str) -> Result<T> {
+        T::deserialize((self.de)(Deserializer::from_str(serial)))
+    }
+
+    fn serialize<T: Serialize>(&self, value: &T) -> String {
+        let mut ser = (self.ser)(Serializer::new(String::new()));
+        value.serialize(&mut ser).unwrap();
+        ser.into_inner()
+    }
+}
+
 #[test]
 fn test_bool() {
     assert_serde!(true, "True");
@@ -52,22 +74,295 @@ fn test_integer() {
     assert_serde!(2u16, "2");
     assert_serde!(3u32, "3");
     assert_serde!(4u64, "4");
-    assert_de_error!(i32, "1.", Error::NotAnInteger);
-    assert_de_error!(i32, "1a", Error::NotAnInteger);
+    assert_de_error!(i32, "1.", ErrorKind::NotAnInteger);
+    assert_de_error!(i32, "1a", ErrorKind::NotAnInteger);
+    assert_de_error!(i32, "", ErrorKind::NotAnInteger);
+    assert_de_error!(u8, "1a", ErrorKind::NotAnUnsignedInteger);
+    assert_de_error!(u8, "-", ErrorKind::NotAnUnsignedInteger);
+    assert_de_error!(u8, "--1", ErrorKind::NotAnUnsignedInteger);
+    assert_de_error!(
+        u8,
+        "300",
+        ErrorKind::IntegerOutOfRange {
+            ty: "u8",
+            min: 0,
+            max: 255
+        }
+    );
+    assert_de_error!(
+        i8,
+        "-129",
+        ErrorKind::IntegerOutOfRange {
+            ty: "i8",
+            min: -128,
+            max: 127
+        }
+    );
+    assert_de_error!(
+        u64,
+        "18446744073709551616",
+        ErrorKind::IntegerOutOfRange {
+            ty: "u64",
+            min: 0,
+            max: u64::MAX as u128
+        }
+    );
+    assert_de_error!(u16, "-1", ErrorKind::NegativeUnsignedInteger { ty: "u16" });
+    assert_eq!(
+        from_str::<u8>("300").unwrap_err().to_string(),
+        "integer out of range for type `u8` (0..=255) (bytes 0..3)"
+    );
+    assert_eq!(
+        from_str::<u8>("-1").unwrap_err().to_string(),
+        "expected a nonnegative integer for type `u8` (bytes 0..2)"
+    );
+}
+
+#[test]
+fn test_integer128() {
+    assert_serde!(-5i128, "-5");
+    assert_serde!(5u128, "5");
+    assert_serde!(i128::MIN, "-170141183460469231731687303715884105728");
+    assert_serde!(i128::MAX, "170141183460469231731687303715884105727");
+    assert_serde!(u128::MAX, "340282366920938463463374607431768211455");
+    assert_de_error!(i128, "1.", ErrorKind::NotAnInteger);
+    assert_de_error!(
+        i128,
+        "170141183460469231731687303715884105728",
+        ErrorKind::IntegerOutOfRange {
+            ty: "i128",
+            min: i128::MIN,
+            max: i128::MAX as u128
+        }
+    );
+    assert_de_error!(
+        u128,
+        "-1",
+        ErrorKind::NegativeUnsignedInteger { ty: "u128" }
+    );
+    assert_de_error!(
+        u128,
+        "340282366920938463463374607431768211456",
+        ErrorKind::IntegerOutOfRange {
+            ty: "u128",
+            min: 0,
+            max: u128::MAX
+        }
+    );
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Run {
+        seed: u128,
+        offset: i128,
+    }
+
+    assert_serde!(
+        Run {
+            seed: 0x0123_4567_89ab_cdef_0123_4567_89ab_cdef,
+            offset: -1
+        },
+        "seed=1512366075204170929049582354406559215,offset=-1"
+    );
+}
+
+#[test]
+fn test_extended_integers() {
+    use crate::Deserializer;
+
+    fn extended<'a, T: Deserialize<'a>>(s: &'a str) -> core::result::Result<T, ErrorKind> {
+        T::deserialize(Deserializer::from_str(s).extended_integers(true)).map_err(Error::into_kind)
+    }
+
+    assert_eq!(extended::<u32>("1_000_000"), Ok(1_000_000));
+    assert_eq!(extended::<u8>("0xff"), Ok(255));
+    assert_eq!(extended::<u8>("0XFF"), Ok(255));
+    assert_eq!(extended::<u16>("0o755"), Ok(0o755));
+    assert_eq!(extended::<u8>("0b1010"), Ok(10));
+    assert_eq!(extended::<u32>("0xdead_beef"), Ok(0xdead_beef));
+    assert_eq!(extended::<u32>("0x_ff"), Ok(255));
+    assert_eq!(extended::<i32>("+42"), Ok(42));
+    assert_eq!(extended::<i32>("-0x10"), Ok(-16));
+    assert_eq!(extended::<i8>("-0b1000_0000"), Ok(-128));
+    assert_eq!(extended::<i32>("1__0_"), Ok(10));
+    assert_eq!(extended::<i32>("_1"), Err(ErrorKind::NotAnInteger));
+    assert_eq!(extended::<i32>("0x"), Err(ErrorKind::NotAnInteger));
+    assert_eq!(extended::<i32>("0x_"), Err(ErrorKind::NotAnInteger));
+    assert_eq!(extended::<i32>("0b102"), Err(ErrorKind::NotAnInteger));
+    assert_eq!(extended::<i32>("+-1"), Err(ErrorKind::NotAnInteger));
+    assert_eq!(extended::<i32>("0x-1"), Err(ErrorKind::NotAnInteger));
+    assert_eq!(
+        extended::<u8>("0x100"),
+        Err(ErrorKind::IntegerOutOfRange {
+            ty: "u8",
+            min: 0,
+            max: 255
+        })
+    );
+    assert_eq!(
+        extended::<u8>("-0x1"),
+        Err(ErrorKind::NegativeUnsignedInteger { ty: "u8" })
+    );
+
+    // Inferred types follow the option.
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(untagged)]
+    enum Mask {
+        Bits(u64),
+        Name(String),
+    }
+
+    assert_eq!(extended::<Mask>("0xff"), Ok(Mask::Bits(255)));
+    assert_eq!(extended::<Mask>("0xfg"), Ok(Mask::Name("0xfg".to_string())));
+
+    // Disabled by default, and the serializer emits plain decimal.
+    assert_de_error!(u32, "1_000", ErrorKind::NotAnUnsignedInteger);
+    assert_de_error!(u8, "0xff", ErrorKind::NotAnUnsignedInteger);
+    assert_eq!(to_string(&0xffu8).unwrap(), "255");
 }
 
 #[test]
 fn test_float() {
     assert_serde!(1f32, "1");
     assert_serde!(2f64, "2");
-    assert_de_error!(f32, "1a", Error::NotAFloatingPointNumber);
+    assert_de_error!(f32, "1a", ErrorKind::NotAFloatingPointNumber);
+    assert_serde!(1e-12f64, "1e-12");
+    assert_serde!(2.5e20f32, "2.5e20");
+    assert_serde!(f64::INFINITY, "inf");
+    assert_serde!(f64::NEG_INFINITY, "-inf");
+    assert_de!(f64::INFINITY, "Infinity");
+    assert_de!(f32::NEG_INFINITY, "-INF");
+    assert_eq!(to_string(&f64::NAN).unwrap(), "NaN");
+    assert!(from_str::<f64>("NaN").unwrap().is_nan());
+    assert!(from_str::<f32>("nan").unwrap().is_nan());
+}
+
+#[test]
+fn test_float_format() {
+    use crate::{FloatFormat, Serializer};
+
+    fn format<T: Serialize>(v: T, format: FloatFormat) -> String {
+        let mut ser = Serializer::new(String::new()).float_format(format);
+        v.serialize(&mut ser).unwrap();
+        ser.into_inner()
+    }
+
+    #[derive(Serialize)]
+    struct Gmres {
+        restart: u32,
+        tol: f64,
+    }
+
+    let gmres = Gmres {
+        restart: 30,
+        tol: 1.25e-6,
+    };
+    assert_eq!(
+        format(&gmres, FloatFormat::Shortest),
+        "restart=30,tol=1.25e-6"
+    );
+    let positional = FloatFormat::Thresholds {
+        lower: -8,
+        upper: 16,
+    };
+    assert_eq!(format(&gmres, positional), "restart=30,tol=0.00000125");
+    assert_eq!(
+        format(&gmres, FloatFormat::Significant(2)),
+        "restart=30,tol=1.3e-6"
+    );
+    assert_eq!(
+        format(&gmres, FloatFormat::Fixed(8)),
+        "restart=30,tol=0.00000125"
+    );
+    assert_eq!(format([1.5f32, -0.25], FloatFormat::Fixed(1)), "1.5,-0.2");
+}
+
+#[test]
+fn test_expressions() {
+    use crate::Deserializer;
+
+    fn eval<'a, T: Deserialize<'a>>(s: &'a str) -> Result<T> {
+        T::deserialize(Deserializer::from_str(s).expressions(true))
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Run {
+        dt: f64,
+        radius: f32,
+        n: u32,
+    }
+
+    assert_eq!(
+        eval::<Run>("dt=1/64,radius=2*pi,n=2**10").unwrap(),
+        Run {
+            dt: 0.015625,
+            radius: 2.0 * core::f32::consts::PI,
+            n: 1024
+        }
+    );
+    assert_eq!(eval::<i32>("-(3 + 4) * 2").unwrap(), -14);
+    assert_eq!(eval::<f64>("1e-3 * 2").unwrap(), 0.002);
+
+    let error = eval::<Run>("dt=1/64,radius=2*tau,n=1").unwrap_err();
+    assert_eq!(error.kind(), &ErrorKind::NotAFloatingPointNumber);
+    assert_eq!(error.span(), Some(17..20));
+    let error = eval::<Run>("dt=1,radius=1,n=2*pi").unwrap_err();
+    assert_eq!(error.kind(), &ErrorKind::NotAnUnsignedInteger);
+    assert_eq!(error.span(), Some(18..20));
+    let error = eval::<i32>("1/2*(3").unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (&ErrorKind::NotAnInteger, Some(4..5))
+    );
+    let error = eval::<i32>("7/0").unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (&ErrorKind::NotAnInteger, Some(1..2))
+    );
+    let u8_out_of_range = ErrorKind::IntegerOutOfRange {
+        ty: "u8",
+        min: 0,
+        max: 255,
+    };
+    assert_eq!(eval::<u8>("2**8").unwrap_err().into_kind(), u8_out_of_range);
+    assert_eq!(
+        eval::<u8>("1-2").unwrap_err().into_kind(),
+        ErrorKind::NegativeUnsignedInteger { ty: "u8" }
+    );
+    let error = eval::<u8>("10**100").unwrap_err();
+    assert_eq!((error.kind(), error.span()), (&u8_out_of_range, Some(2..4)));
+
+    // Deeply nested expressions are rejected rather than overflowing the stack.
+    let nested = ["(".repeat(200000), "1".to_string(), ")".repeat(200000)].concat();
+    assert_eq!(
+        eval::<f64>(&nested).unwrap_err().into_kind(),
+        ErrorKind::NotAFloatingPointNumber
+    );
+    assert_eq!(
+        eval::<i64>(&nested).unwrap_err().into_kind(),
+        ErrorKind::NotAnInteger
+    );
+
+    // Inferred types and the default deserializer only accept literals.
+    assert_eq!(eval::<crate::Value>("2*3").unwrap(), "2*3");
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(untagged)]
+    enum Count {
+        Number(u64),
+        Text(String),
+    }
+    assert_eq!(
+        eval::<Count>("2*3").unwrap(),
+        Count::Text("2*3".to_string())
+    );
+    assert_de_error!(f64, "1/64", ErrorKind::NotAFloatingPointNumber);
+    assert_de_error!(u32, "2**10", ErrorKind::NotAnUnsignedInteger);
 }
 
 #[test]
 fn test_char() {
     assert_serde!('a', "a");
-    assert_de_error!(char, "ab", Error::NotASingleCharacter);
-    assert_de_error!(char, "", Error::NotASingleCharacter);
+    assert_de_error!(char, "ab", ErrorKind::NotASingleCharacter);
+    assert_de_error!(char, "", ErrorKind::NotASingleCharacter);
 }
 
 #[test]
@@ -94,11 +389,11 @@ fn test_option() {
 #[test]
 fn test_tuple() {
     assert_serde!((), "");
-    assert_de_error!((), "a", Error::UnexpectedValueForUnit);
+    assert_de_error!((), "a", ErrorKind::UnexpectedValueForUnit);
 
     assert_serde!(("".to_string(),), "{}");
     assert_serde!((1i32,), "1");
-    assert_de_error!((i32,), "1,2", Error::TooManyElements);
+    assert_de_error!((i32,), "1,2", ErrorKind::TooManyElements);
 
     assert_serde!(("".to_string(), "".to_string()), "{},{}");
     assert_serde!((1i32, 2f64), "1,2");
@@ -112,7 +407,7 @@ fn test_struct() {
     struct S;
 
     assert_serde!(S, "");
-    assert_de_error!(S, "a", Error::UnexpectedValueForUnit);
+    assert_de_error!(S, "a", ErrorKind::UnexpectedValueForUnit);
 
     #[derive(Debug, PartialEq, Serialize, Deserialize)]
     struct Ss(String);
@@ -130,13 +425,13 @@ fn test_struct() {
     struct Si(i32);
 
     assert_serde!(Si(1), "1");
-    assert_de_error!(Si, "1,2", Error::NotAnInteger);
+    assert_de_error!(Si, "1,2", ErrorKind::NotAnInteger);
 
     #[derive(Debug, PartialEq, Serialize, Deserialize)]
     struct Sif(i32, f64);
 
     assert_serde!(Sif(1, 1.), "1,1");
-    assert_de_error!(Sif, "1,2,3", Error::TooManyElements);
+    assert_de_error!(Sif, "1,2,3", ErrorKind::TooManyElements);
 }
 
 #[test]
@@ -151,12 +446,12 @@ fn test_enum() {
     }
 
     assert_serde!(Test::A, "A");
-    assert_de_error!(Test, "A{a}", Error::UnexpectedValueForUnit);
+    assert_de_error!(Test, "A{a}", ErrorKind::UnexpectedValueForUnit);
 
     assert_serde!(Test::B("".to_string()), "B");
     assert_serde!(Test::B("1".to_string()), "B{1}");
     assert_serde!(Test::C(1), "C{1}");
-    assert_de_error!(Test, "C{1,2}", Error::NotAnInteger);
+    assert_de_error!(Test, "C{1,2}", ErrorKind::NotAnInteger);
 
     assert_serde!(Test::D("".to_string(), "".to_string()), "D{{},{}}");
     assert_serde!(Test::D("1".to_string(), "2".to_string()), "D{1,2}");
@@ -165,7 +460,7 @@ fn test_enum() {
         "D{{{<}>},{<{>}}}"
     );
     assert_serde!(Test::E(1, 2.), "E{1,2}");
-    assert_de_error!(Test, "E{1,2,3}", Error::TooManyElements);
+    assert_de_error!(Test, "E{1,2,3}", ErrorKind::TooManyElements);
 }
 
 #[test]
@@ -198,3 +493,1460 @@ fn test_map() {
     m2.insert("C".to_string(), vec![2i32, 3i32]);
     assert_serde!(m2, "A=,B=1,C={2,3}");
 }
+
+#[test]
+fn test_unknown_fields() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct S {
+        a: i32,
+    }
+
+    assert_de!(S { a: 1 }, "a=1,b=2");
+    assert_de!(S { a: 1 }, "b={x,{y=}},a=1");
+    assert_de!(S { a: 1 }, "b={<{>}},a=1");
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct D {
+        a: i32,
+    }
+
+    assert_de!(D { a: 1 }, "a=1");
+    assert_de_error!(
+        D,
+        "a=1,b=2",
+        ErrorKind::Message("unknown field `b`, expected `a`".to_string())
+    );
+}
+
+#[test]
+fn test_unsupported() {
+    use serde::de::{Deserializer as _, IgnoredAny};
+
+    assert_eq!(
+        crate::Deserializer::from_str("a")
+            .deserialize_bytes(IgnoredAny)
+            .map_err(Error::into_kind),
+        Err(ErrorKind::NotEncodedBytes(crate::BytesEncoding::Hex))
+    );
+    assert_eq!(
+        crate::Deserializer::from_str("a")
+            .deserialize_byte_buf(IgnoredAny)
+            .map_err(Error::into_kind),
+        Err(ErrorKind::NotEncodedBytes(crate::BytesEncoding::Hex))
+    );
+
+    // A (malformed) `Deserialize` implementation that requests a map value
+    // before the key.
+    #[derive(Debug, PartialEq)]
+    struct ValueFirst;
+
+    impl<'de> Deserialize<'de> for ValueFirst {
+        fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct Visitor;
+
+            impl<'de> serde::de::Visitor<'de> for Visitor {
+                type Value = ValueFirst;
+
+                fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    f.write_str("a map")
+                }
+
+                fn visit_map<A>(self, mut map: A) -> core::result::Result<ValueFirst, A::Error>
+                where
+                    A: serde::de::MapAccess<'de>,
+                {
+                    map.next_value::<IgnoredAny>()?;
+                    Ok(ValueFirst)
+                }
+            }
+
+            deserializer.deserialize_map(Visitor)
+        }
+    }
+
+    assert_de_error!(ValueFirst, "a=1", ErrorKind::ValueBeforeKey);
+}
+
+// Deserializes every string up to length 5 composed of characters that have a
+// special meaning in Stringly into a variety of types. The test passes if no
+// deserialization panics, regardless of the result.
+#[cfg(feature = "std")]
+#[test]
+fn test_no_panic() {
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    enum E {
+        A,
+        B(char),
+        C(u8, bool),
+        D { x: f64 },
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct S<'a> {
+        a: Option<i32>,
+        #[serde(borrow)]
+        b: Vec<&'a str>,
+        c: E,
+        d: (),
+    }
+
+    fn check<'a, T: Deserialize<'a>>(s: &'a str) {
+        let _ = from_str::<T>(s);
+    }
+
+    let chars = ['{', '}', '<', '>', ',', '=', 'a', '1'];
+    let mut s = String::new();
+    for length in 0..6u32 {
+        for i in 0..chars.len().pow(length) {
+            s.clear();
+            let mut k = i;
+            for _j in 0..length {
+                s.push(chars[k % chars.len()]);
+                k /= chars.len();
+            }
+            check::<S>(&s);
+            check::<E>(&s);
+            check::<Vec<Option<(i32, String)>>>(&s);
+            check::<std::collections::BTreeMap<String, Vec<u8>>>(&s);
+            check::<Option<Option<char>>>(&s);
+            check::<serde::de::IgnoredAny>(&s);
+        }
+    }
+}
+
+#[test]
+fn test_error_location() {
+    use crate::PathSegment::{Index, Key};
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Solver {
+        tolerances: Vec<f64>,
+        restart: Option<u32>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        name: String,
+        solver: Solver,
+    }
+
+    let s = "name=a,solver={tolerances={1,2,x},restart=3}";
+    let error = from_str::<Config>(s).unwrap_err();
+    assert_eq!(*error.kind(), ErrorKind::NotAFloatingPointNumber);
+    assert_eq!(error.span(), Some(31..32));
+    assert_eq!(
+        error.path(),
+        [Key("solver".into()), Key("tolerances".into()), Index(2)]
+    );
+    assert_eq!(
+        error.to_string(),
+        "expected a floating point number at `solver.tolerances[2]` (bytes 31..32)"
+    );
+
+    let s = "name=a,solver={tolerances=1,restart={-1}}";
+    let error = from_str::<Config>(s).unwrap_err();
+    assert_eq!(
+        *error.kind(),
+        ErrorKind::NegativeUnsignedInteger { ty: "u32" }
+    );
+    assert_eq!(&s[error.span().unwrap()], "-1");
+    assert_eq!(
+        error.to_string(),
+        "expected a nonnegative integer for type `u32` at `solver.restart` (bytes 37..39)"
+    );
+
+    let s = "name=a,solver={restart=1}";
+    let error = from_str::<Config>(s).unwrap_err();
+    assert_eq!(&s[error.span().unwrap()], "restart=1");
+    assert_eq!(error.path(), [Key("solver".into())]);
+
+    let s = "name=a,solver={tolerances=1,restart=1},x";
+    let error = from_str::<Config>(s).unwrap_err();
+    assert_eq!(*error.kind(), ErrorKind::NotAKeyValuePair);
+    assert_eq!(&s[error.span().unwrap()], "x");
+    assert_eq!(error.path(), []);
+
+    let error = from_str::<(i32, i32)>("1,2,3").unwrap_err();
+    assert_eq!(*error.kind(), ErrorKind::TooManyElements);
+    assert_eq!(error.span(), Some(4..5));
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    enum E {
+        A { a: Vec<i32> },
+        B(i32, i32),
+    }
+
+    let error = from_str::<Vec<E>>("B{1,2},A{a={1,x}}").unwrap_err();
+    assert_eq!(error.path(), [Index(1), Key("a".into()), Index(1)]);
+    assert_eq!(error.span(), Some(14..15));
+    let error = from_str::<E>("C{1}").unwrap_err();
+    assert_eq!(error.span(), Some(0..1));
+}
+
+#[test]
+fn test_error_render() {
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    enum Solver {
+        Gmres { restart: u32, tol: f64 },
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        name: String,
+        solvers: Vec<Solver>,
+    }
+
+    let s = "name=a\nb,solvers={Gmres{restart=30,tol=1e-8},Gmres{restart=-1,tol=0}}";
+    let error = from_str::<Config>(s).unwrap_err();
+    assert_eq!(
+        error.render(s),
+        "\
+error: expected a nonnegative integer for type `u32`
+ --> line 2, column 53, at `solvers[1].restart`
+  |
+2 | b,solvers={Gmres{restart=30,tol=1e-8},Gmres{restart=-1,tol=0}}
+  |                                                     ^^ expected a nonnegative integer for type `u32`
+  |                                            ------------------ in protected context at level 2
+  |           ---------------------------------------------------- in protected context at level 1
+"
+    );
+
+    // The braces of balancers do not delimit contexts.
+    let s = "v={<{>x,a}},w={1,{b{<}>}}";
+    let error = from_str::<BTreeMap<String, (u32, String)>>(s).unwrap_err();
+    assert_eq!(
+        error.render(s),
+        "\
+error: expected an unsigned integer
+ --> line 1, column 7, at `v[0]`
+  |
+1 | v={<{>x,a}},w={1,{b{<}>}}
+  |       ^ expected an unsigned integer
+  |   --------- in protected context at level 1
+"
+    );
+    let s = "w={1,{b{<}>}},v={<{>x,{a}}}";
+    let error = from_str::<BTreeMap<String, (u32, String)>>(s).unwrap_err();
+    assert_eq!(
+        error.render(s),
+        "\
+error: expected an unsigned integer
+ --> line 1, column 21, at `v[0]`
+  |
+1 | w={1,{b{<}>}},v={<{>x,{a}}}
+  |                     ^ expected an unsigned integer
+  |                 ----------- in protected context at level 1
+"
+    );
+
+    let s = "name=abc";
+    let error = from_str::<Config>(s).unwrap_err();
+    assert_eq!(
+        error.render(s),
+        "\
+error: missing field `solvers`
+ --> line 1, column 1
+  |
+1 | name=abc
+  | ^^^^^^^^ missing field `solvers`
+"
+    );
+
+    let error = from_str::<(u8,)>("").unwrap_err();
+    assert_eq!(
+        error.render(""),
+        "\
+error: invalid length 0, expected a tuple of size 1
+ --> line 1, column 1
+  |
+1 | \n  | ^ invalid length 0, expected a tuple of size 1
+"
+    );
+
+    let error: Error = ErrorKind::WriteFailed.into();
+    assert_eq!(error.render("a"), "error: failed to write the output\n");
+
+    // A span that does not fall on character boundaries of the input.
+    let error = from_str::<u32>("x").unwrap_err();
+    assert_eq!(error.render("é"), "error: expected an unsigned integer\n");
+}
+
+#[test]
+fn test_pretty() {
+    use crate::{from_str_pretty, to_string_pretty};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Solver {
+        Direct,
+        Gmres { restart: u32, tol: f64 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Config {
+        name: String,
+        solvers: Vec<Solver>,
+        shape: (u32, u32),
+    }
+
+    let config = Config {
+        name: "solver".to_string(),
+        solvers: vec![
+            Solver::Direct,
+            Solver::Gmres {
+                restart: 30,
+                tol: 0.5,
+            },
+        ],
+        shape: (3, 3),
+    };
+    let pretty = "\
+name=solver
+solvers=
+  Direct
+  Gmres
+    restart=30
+    tol=0.5
+shape=
+  3
+  3";
+    assert_eq!(to_string_pretty(&config).unwrap(), pretty);
+    assert_eq!(from_str_pretty::<Config>(pretty).unwrap(), config);
+
+    let pretty = "\
+name=a
+solvers=
+  Gmres
+    restart=-1
+    tol=0
+shape=
+  3
+  3";
+    let error = from_str_pretty::<Config>(pretty).unwrap_err();
+    assert_eq!(&pretty[error.span().unwrap()], "-1");
+    assert_eq!(
+        error.render(pretty),
+        "\
+error: expected a nonnegative integer for type `u32`
+ --> line 4, column 13, at `solvers[0].restart`
+  |
+4 |     restart=-1
+  |             ^^ expected a nonnegative integer for type `u32`
+"
+    );
+
+    let error = from_str_pretty::<Config>("name=a\nsolvers=\n  Direct\n").unwrap_err();
+    assert_eq!(
+        &"name=a\nsolvers=\n  Direct\n"[error.span().unwrap()],
+        "name=a\nsolvers=\n  Direct"
+    );
+}
+
+// Deserializes through `deserialize_any`, by means of an untagged enum.
+fn infer<'a, T: Deserialize<'a>>(serial: &'a str) -> Result<T> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Untagged<T> {
+        Value(T),
+    }
+
+    from_str(serial).map(|Untagged::Value(value)| value)
+}
+
+macro_rules! assert_infer {
+    ($value:expr, $serial:literal) => {
+        assert_eq!(to_string(&$value).unwrap(), $serial);
+        assert_eq!(infer($serial).ok(), Some($value));
+    };
+}
+
+#[test]
+fn test_any() {
+    // The unambiguous round-trip tests.
+    assert_infer!(true, "True");
+    assert_infer!(false, "False");
+    assert_infer!(1i8, "1");
+    assert_infer!(4i64, "4");
+    assert_infer!(4u64, "4");
+    assert_infer!(-4i64, "-4");
+    assert_infer!(1f32, "1");
+    assert_infer!(2.5f64, "2.5");
+    assert_infer!('a', "a");
+    assert_infer!("abc".to_string(), "abc");
+    assert_infer!(Some("a".to_string()), "a");
+    assert_infer!(Some(1i32), "1");
+    assert_infer!(None as Option<i32>, "");
+    assert_infer!((), "");
+    assert_infer!((1i32, 2f64), "1,2");
+    assert_infer!(("{".to_string(), "}".to_string()), "{{<}>},{<{>}}");
+    assert_infer!(vec![1i32, 2i32, 3i32], "1,2,3");
+    assert_infer!(vec!["a".to_string(), "b".to_string()], "a,b");
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sif(i32, f64);
+
+    assert_infer!(Sif(1, 1.), "1,1");
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Test {
+        A,
+        B(String),
+        C(i32),
+        D(String, String),
+        E(i32, f64),
+        F { a: i32, b: Vec<String> },
+    }
+
+    assert_infer!(Test::A, "A");
+    assert_infer!(Test::B("x".to_string()), "B{x}");
+    assert_infer!(Test::C(1), "C{1}");
+    assert_infer!(Test::D("x".to_string(), "y".to_string()), "D{x,y}");
+    assert_infer!(
+        Test::D("{".to_string(), "}".to_string()),
+        "D{{{<}>},{<{>}}}"
+    );
+    assert_infer!(Test::E(1, 2.), "E{1,2}");
+    assert_infer!(
+        Test::F {
+            a: 1,
+            b: vec!["x".to_string(), "y".to_string()]
+        },
+        "F{a=1,b={x,y}}"
+    );
+
+    #[cfg(feature = "std")]
+    {
+        let mut m = std::collections::BTreeMap::new();
+        m.insert("A".to_string(), vec![1i32, 4i32]);
+        m.insert("1".to_string(), vec![2i32, 3i32]);
+        assert_infer!(m, "1={2,3},A={1,4}");
+    }
+
+    // The serialization of these values is ambiguous and infers differently.
+    assert!(infer::<String>("").is_err());
+    assert!(infer::<String>("1").is_err());
+    assert!(infer::<String>("a=b").is_err());
+    assert!(infer::<Vec<i32>>("1").is_err());
+    assert!(infer::<Option<String>>("{}").is_err());
+    assert!(infer::<Vec<String>>("{},{}").is_err());
+
+    // Deeply nested values are rejected rather than overflowing the stack.
+    let nested = ["{".repeat(200000), "a".to_string(), "}".repeat(200000)].concat();
+    assert_eq!(
+        infer::<String>(&nested).unwrap_err().into_kind(),
+        ErrorKind::NestedTooDeeply
+    );
+    let nested = ["a=".repeat(200000), "a".to_string()].concat();
+    assert_eq!(
+        infer::<String>(&nested).unwrap_err().into_kind(),
+        ErrorKind::NestedTooDeeply
+    );
+    let nested = ["A{".repeat(200000), "a".to_string(), "}".repeat(200000)].concat();
+    assert_eq!(
+        infer::<String>(&nested).unwrap_err().into_kind(),
+        ErrorKind::NestedTooDeeply
+    );
+    let nested = ["{".repeat(100), "a".to_string(), "}".repeat(100)].concat();
+    assert!(infer::<serde::de::IgnoredAny>(&nested).is_ok());
+
+    // Untagged enums.
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(untagged)]
+    enum Tol {
+        Value(f64),
+        Preset(String),
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Opts {
+        tol: Tol,
+    }
+
+    assert_de!(
+        Opts {
+            tol: Tol::Value(1e-6)
+        },
+        "tol=1e-6"
+    );
+    assert_de!(
+        Opts {
+            tol: Tol::Value(1.)
+        },
+        "tol=1"
+    );
+    assert_de!(
+        Opts {
+            tol: Tol::Preset("auto".to_string())
+        },
+        "tol=auto"
+    );
+    assert_de!(
+        Opts {
+            tol: Tol::Preset("nan".to_string())
+        },
+        "tol=nan"
+    );
+}
+
+#[test]
+fn test_internally_tagged() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ilu {
+        fill: u32,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    enum Solver {
+        Direct,
+        Gmres {
+            restart: u32,
+            tol: f64,
+            precon: Option<String>,
+        },
+        Cg(Ilu),
+    }
+
+    assert_serde!(Solver::Direct, "type=direct");
+    assert_serde!(
+        Solver::Gmres {
+            restart: 30,
+            tol: 1e-8,
+            precon: None
+        },
+        "type=gmres,restart=30,tol=1e-8,precon="
+    );
+    assert_de!(
+        Solver::Gmres {
+            restart: 30,
+            tol: 1e-8,
+            precon: Some("ilu".to_string())
+        },
+        "restart=30,type=gmres,tol=1e-8,precon=ilu"
+    );
+    assert_serde!(Solver::Cg(Ilu { fill: 2 }), "type=cg,fill=2");
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        solver: Solver,
+        shape: (u32, u32),
+    }
+
+    assert_serde!(
+        Config {
+            solver: Solver::Cg(Ilu { fill: 2 }),
+            shape: (3, 3)
+        },
+        "solver={type=cg,fill=2},shape={3,3}"
+    );
+    assert_de_error!(
+        Solver,
+        "type=bicg",
+        ErrorKind::Message(
+            "unknown variant `bicg`, expected one of `direct`, `gmres`, `cg`".to_string()
+        )
+    );
+    assert_eq!(
+        from_str::<Config>("solver={type=direct},shape={3,3}")
+            .unwrap()
+            .solver,
+        Solver::Direct
+    );
+
+    // By default, string fields only accept text that is inferred as a string.
+    assert_de_error!(
+        Solver,
+        "type=gmres,restart=30,tol=1e-8,precon=1",
+        ErrorKind::Message("invalid type: integer `1`, expected a string".to_string())
+    );
+    assert_de_error!(
+        Solver,
+        "type=gmres,restart=30,tol=1e-8,precon=true",
+        ErrorKind::Message("invalid type: boolean `true`, expected a string".to_string())
+    );
+
+    // Value fields accept any text.
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    enum Precon<'a> {
+        Ilu {
+            #[serde(borrow)]
+            name: crate::Value<'a>,
+        },
+    }
+
+    assert_de!(
+        Precon::Ilu {
+            name: crate::Value::from("1")
+        },
+        "type=ilu,name=1"
+    );
+
+    // With verbatim scalars, string fields accept any text and value fields
+    // receive it exactly.
+    let verbatim = Configured {
+        de: |de| de.verbatim_scalars(true),
+        ser: |ser| ser,
+    };
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    enum Named {
+        Ilu { name: String },
+    }
+
+    for name in ["1", "true", "1.10", "a"] {
+        assert_eq!(
+            verbatim.deserialize::<Named>(&format!("type=ilu,name={}", name)),
+            Ok(Named::Ilu {
+                name: name.to_string()
+            })
+        );
+    }
+    assert_eq!(
+        verbatim.deserialize::<Precon>("type=ilu,name=1.10"),
+        Ok(Precon::Ilu {
+            name: crate::Value::from("1.10")
+        })
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_flatten() {
+    use crate::value::Value;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct SolverOpts {
+        tol: f64,
+        restart: Option<u32>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct MeshOpts {
+        nelems: u32,
+        shape: (u32, u32),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Run {
+        name: String,
+        #[serde(flatten)]
+        solver: SolverOpts,
+        #[serde(flatten)]
+        mesh: MeshOpts,
+    }
+
+    let run = Run {
+        name: "a,b".to_string(),
+        solver: SolverOpts {
+            tol: 0.5,
+            restart: Some(30),
+        },
+        mesh: MeshOpts {
+            nelems: 10,
+            shape: (3, 4),
+        },
+    };
+    assert_serde!(run, "name={a,b},tol=0.5,restart=30,nelems=10,shape={3,4}");
+    assert_de!(run, "shape={3,4},tol=0.5,nelems=10,name={a,b},restart=30");
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Rest<'a> {
+        nelems: u32,
+        #[serde(flatten, borrow)]
+        rest: BTreeMap<String, Value<'a>>,
+    }
+
+    let mut rest = BTreeMap::new();
+    rest.insert("basis".to_string(), Value::from("spline"));
+    rest.insert("title".to_string(), Value::from("a b"));
+    assert_serde!(Rest { nelems: 4, rest }, "nelems=4,basis=spline,title=a b");
+    assert_de!(
+        Rest {
+            nelems: 4,
+            rest: BTreeMap::new()
+        },
+        "nelems=4"
+    );
+
+    // The remaining values are inferred and written back as text.
+    let mut rest = BTreeMap::new();
+    rest.insert("degree".to_string(), Value::from("2"));
+    rest.insert("shift".to_string(), Value::from("-1"));
+    rest.insert("tol".to_string(), Value::from("0.5"));
+    rest.insert("refine".to_string(), Value::from("True"));
+    rest.insert("name".to_string(), Value::from("x"));
+    rest.insert("empty".to_string(), Value::from(""));
+    rest.insert("shape".to_string(), Value::from("3,4"));
+    rest.insert("opts".to_string(), Value::from("a=1,b=x"));
+    assert_de!(
+        Rest { nelems: 4, rest },
+        "nelems=4,degree=2,shift=-1,tol=0.5,refine=true,name=x,empty=,shape={3,4},opts={a=1,b=x}"
+    );
+
+    // With verbatim scalars, the remaining values are caught as their exact
+    // text, also by maps of strings and by string fields.
+    let verbatim = Configured {
+        de: |de| de.verbatim_scalars(true),
+        ser: |ser| ser,
+    };
+    let serial = "nelems=4,version=1.10,id=007,big=99999999999999999999999,huge=1e400,flag=true";
+    let rest = verbatim.deserialize::<Rest>(serial).unwrap().rest;
+    for (key, value) in serial
+        .split(',')
+        .skip(1)
+        .filter_map(|item| item.split_once('='))
+    {
+        assert_eq!(rest[key], value);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Text {
+        nelems: u32,
+        #[serde(flatten)]
+        rest: BTreeMap<String, String>,
+    }
+
+    let rest = verbatim
+        .deserialize::<Text>("nelems=4,degree=2,flag=true,name=x")
+        .unwrap()
+        .rest;
+    assert_eq!(
+        (&*rest["degree"], &*rest["flag"], &*rest["name"]),
+        ("2", "true", "x")
+    );
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Label {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Job {
+        nelems: u32,
+        #[serde(flatten)]
+        label: Label,
+    }
+
+    assert_eq!(
+        verbatim.deserialize::<Job>("nelems=4,name=42"),
+        Ok(Job {
+            nelems: 4,
+            label: Label {
+                name: "42".to_string()
+            }
+        })
+    );
+}
+
+#[test]
+fn test_bytes() {
+    use crate::{BytesEncoding, Deserializer, Serializer};
+
+    // A byte buffer, like `serde_bytes::ByteBuf`.
+    #[derive(Debug, PartialEq)]
+    struct Bytes(Vec<u8>);
+
+    impl Serialize for Bytes {
+        fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Bytes {
+        fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct Visitor;
+
+            impl serde::de::Visitor<'_> for Visitor {
+                type Value = Bytes;
+
+                fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    f.write_str("bytes")
+                }
+
+                fn visit_byte_buf<E>(self, v: Vec<u8>) -> core::result::Result<Bytes, E> {
+                    Ok(Bytes(v))
+                }
+            }
+
+            deserializer.deserialize_byte_buf(Visitor)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Key {
+        name: String,
+        hash: Bytes,
+    }
+
+    let key = Key {
+        name: "a".to_string(),
+        hash: Bytes(vec![0, 1, 254, 255]),
+    };
+    assert_serde!(key, "name=a,hash=0001feff");
+    assert_de!(key, "name=a,hash=0001FEFF");
+    assert_serde!(Bytes(vec![]), "");
+    assert_de_error!(
+        Key,
+        "name=a,hash=0001fef",
+        ErrorKind::NotEncodedBytes(BytesEncoding::Hex)
+    );
+
+    let mut ser = Serializer::new(String::new()).bytes_encoding(BytesEncoding::Base64);
+    key.serialize(&mut ser).unwrap();
+    assert_eq!(ser.into_inner(), "name=a,hash=AAH+/w==");
+    let de = Deserializer::from_str("name=a,hash=AAH+/w==").bytes_encoding(BytesEncoding::Base64);
+    assert_eq!(Key::deserialize(de).unwrap(), key);
+    let de = Deserializer::from_str("name=a,hash=00-1").bytes_encoding(BytesEncoding::Base64);
+    let error = Key::deserialize(de).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected base64 encoded bytes at `hash` (bytes 12..16)"
+    );
+}
+
+#[test]
+fn test_bare_flags() {
+    use std::collections::BTreeMap;
+
+    let flags = Configured {
+        de: |de| de.bare_flags(true),
+        ser: |ser| ser.bare_flags(true),
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Args {
+        verbose: bool,
+        cache: bool,
+        tol: f64,
+        dry_run: Option<()>,
+        #[serde(rename = "no-color")]
+        no_color: bool,
+    }
+
+    let args = Args {
+        verbose: true,
+        cache: false,
+        tol: 1e-6,
+        dry_run: Some(()),
+        no_color: true,
+    };
+    assert_eq!(
+        flags.deserialize::<Args>("verbose,no-cache,tol=1e-6,dry_run,no-color"),
+        Ok(args)
+    );
+    let args = flags
+        .deserialize::<Args>("verbose=False,cache=True,tol=1,dry_run=,no-color=no")
+        .unwrap();
+    assert_eq!(
+        args,
+        Args {
+            verbose: false,
+            cache: true,
+            tol: 1.0,
+            dry_run: None,
+            no_color: false,
+        }
+    );
+    assert_eq!(
+        flags
+            .deserialize::<Args>("verbose,cache,tol=1,no-dry_run,no-color")
+            .unwrap()
+            .dry_run,
+        None
+    );
+
+    // Round trip through the serializer.
+    let args = Args {
+        verbose: true,
+        cache: false,
+        tol: 1e-6,
+        dry_run: Some(()),
+        no_color: true,
+    };
+    let s = flags.serialize(&args);
+    assert_eq!(s, "verbose,cache=False,tol=1e-6,dry_run,no-color");
+    assert_eq!(flags.deserialize::<Args>(&s), Ok(args));
+    assert_eq!(
+        to_string(&Args {
+            verbose: true,
+            cache: false,
+            tol: 1.0,
+            dry_run: None,
+            no_color: false,
+        })
+        .unwrap(),
+        "verbose=True,cache=False,tol=1,dry_run=,no-color=False"
+    );
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Solver {
+        Cg { precon: bool, restart: Option<bool> },
+    }
+
+    let cg = Solver::Cg {
+        precon: true,
+        restart: Some(true),
+    };
+    assert_eq!(flags.serialize(&cg), "Cg{precon,restart}");
+    assert_eq!(flags.deserialize::<Solver>("Cg{precon,restart}"), Ok(cg));
+    assert_eq!(flags.serialize(&vec![true, false]), "True,False");
+
+    // Maps negate any `no-` key.
+    assert_eq!(
+        flags.deserialize::<BTreeMap<String, bool>>("a,no-b"),
+        Ok([("a".to_string(), true), ("b".to_string(), false)].into())
+    );
+
+    // A bare key for any other type, or without the option, is an error.
+    let error = flags
+        .deserialize::<Args>("verbose,cache,tol,dry_run,no-color")
+        .unwrap_err();
+    assert_eq!(error.kind(), &ErrorKind::NotAKeyValuePair);
+    assert_eq!(error.span(), Some(14..17));
+    assert_eq!(error.path(), [crate::PathSegment::Key("tol".into())]);
+    assert_de_error!(
+        Args,
+        "verbose,cache=True,tol=1,dry_run=,no-color=False",
+        ErrorKind::NotAKeyValuePair
+    );
+}
+
+#[test]
+fn test_positional_fields() {
+    use crate::PathSegment;
+
+    let positional = Configured {
+        de: |de| de.positional_fields(true),
+        ser: |ser| ser.positional_fields(4),
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Solver {
+        Gmres { restart: u32, tol: f64 },
+        Direct,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        solver: Solver,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+        verbose: bool,
+    }
+
+    let gmres = Solver::Gmres {
+        restart: 30,
+        tol: 1e-8,
+    };
+    assert_eq!(
+        positional
+            .deserialize::<Solver>("Gmres{30,tol=1e-8}")
+            .as_ref(),
+        Ok(&gmres)
+    );
+    assert_eq!(
+        positional.deserialize::<Solver>("Gmres{30,1e-8}").as_ref(),
+        Ok(&gmres)
+    );
+    assert_eq!(
+        positional
+            .deserialize::<Solver>("Gmres{restart=30,tol=1e-8}")
+            .as_ref(),
+        Ok(&gmres)
+    );
+    let config = Config {
+        name: "a=b,c".to_string(),
+        solver: gmres,
+        tags: vec![],
+        verbose: true,
+    };
+    assert_eq!(
+        positional
+            .deserialize::<Config>("{a=b,c},Gmres{30,tol=1e-8},verbose=yes")
+            .as_ref(),
+        Ok(&config)
+    );
+
+    // Round trip through the serializer, which protects `=` in positional
+    // values and switches to keys after a skipped field.
+    let s = positional.serialize(&config);
+    assert_eq!(s, "{a=b,c},Gmres{30,1e-8},verbose=True");
+    assert_eq!(positional.deserialize::<Config>(&s).as_ref(), Ok(&config));
+    assert_eq!(
+        Configured {
+            ser: |ser| ser.positional_fields(1),
+            ..positional
+        }
+        .serialize(&config),
+        "{a=b,c},solver=Gmres{30,tol=1e-8},verbose=True"
+    );
+    assert_eq!(
+        to_string(&config).unwrap(),
+        "name={a=b,c},solver=Gmres{restart=30,tol=1e-8},verbose=True"
+    );
+    assert_eq!(positional.serialize(&Solver::Direct), "Direct");
+
+    // Errors.
+    let error = positional
+        .deserialize::<Solver>("Gmres{30,restart=40}")
+        .unwrap_err();
+    assert_eq!(
+        error.kind(),
+        &ErrorKind::PositionalAndNamedField { field: "restart" }
+    );
+    assert_eq!(error.span(), Some(9..16));
+    assert_eq!(
+        error.to_string(),
+        "field `restart` given both by position and by key (bytes 9..16)"
+    );
+    let error = positional
+        .deserialize::<Solver>("Gmres{30,1,2}")
+        .unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (&ErrorKind::TooManyElements, Some(11..12))
+    );
+    let error = positional
+        .deserialize::<Solver>("Gmres{x,tol=1}")
+        .unwrap_err();
+    assert_eq!(error.kind(), &ErrorKind::NotAnUnsignedInteger);
+    assert_eq!(error.path(), [PathSegment::Key("restart".into())]);
+    let error = positional
+        .deserialize::<Solver>("Gmres{tol=1,30}")
+        .unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (&ErrorKind::NotAKeyValuePair, Some(12..14))
+    );
+    assert_de_error!(Solver, "Gmres{30,tol=1}", ErrorKind::NotAKeyValuePair);
+}
+
+#[test]
+fn test_dotted_keys() {
+    use crate::PathSegment;
+    use std::collections::BTreeMap;
+
+    let dotted = Configured {
+        de: |de| de.dotted_keys(true),
+        ser: |ser| ser.dotted_keys(true),
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Solver {
+        tol: f64,
+        maxiter: u32,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Mesh {
+        nelems: u32,
+        #[serde(default)]
+        degree: u8,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        solver: Solver,
+        #[serde(default)]
+        meshes: Vec<Mesh>,
+        #[serde(default)]
+        fallback: Option<Solver>,
+    }
+
+    let config = Config {
+        name: "a.b".to_string(),
+        solver: Solver {
+            tol: 1e-6,
+            maxiter: 100,
+        },
+        meshes: vec![],
+        fallback: None,
+    };
+    assert_eq!(
+        dotted
+            .deserialize::<Config>("solver.tol=1e-6,name=a.b,solver.maxiter=100")
+            .as_ref(),
+        Ok(&config)
+    );
+    assert_eq!(
+        dotted
+            .deserialize::<Config>("name=a.b,solver={tol=1e-6,maxiter=100}")
+            .as_ref(),
+        Ok(&config)
+    );
+    assert_de_error!(
+        Config,
+        "name=a.b,solver.tol=1e-6,solver.maxiter=100",
+        ErrorKind::Message("missing field `solver`".to_string())
+    );
+
+    // Index keys, in any order, and nesting.
+    let config: Config = dotted
+        .deserialize(
+            "name=x,meshes.1.nelems=8,solver.tol=1,meshes.0.nelems=4,meshes.1.degree=2,\
+         solver.maxiter=1,fallback.tol=0.5,fallback.maxiter=10",
+        )
+        .unwrap();
+    assert_eq!(
+        config.meshes,
+        [
+            Mesh {
+                nelems: 4,
+                degree: 0
+            },
+            Mesh {
+                nelems: 8,
+                degree: 2
+            }
+        ]
+    );
+    assert_eq!(
+        config.fallback,
+        Some(Solver {
+            tol: 0.5,
+            maxiter: 10
+        })
+    );
+    assert_eq!(
+        dotted.deserialize::<BTreeMap<String, BTreeMap<String, u32>>>(
+            "a.x=1,b=,a.y=2,{c.d}=,{e.f}.g=3"
+        ),
+        Ok(BTreeMap::from([
+            (
+                "a".to_string(),
+                BTreeMap::from([("x".to_string(), 1), ("y".to_string(), 2)])
+            ),
+            ("b".to_string(), BTreeMap::new()),
+            ("c.d".to_string(), BTreeMap::new()),
+            ("e.f".to_string(), BTreeMap::from([("g".to_string(), 3)])),
+        ]))
+    );
+
+    // Round trip through the serializer.
+    let s = dotted.serialize(&config);
+    assert_eq!(
+        s,
+        "name=x,solver.tol=1,solver.maxiter=1,meshes={{nelems=4,degree=0},{nelems=8,degree=2}},\
+         fallback.tol=0.5,fallback.maxiter=10"
+    );
+    assert_eq!(dotted.deserialize::<Config>(&s).as_ref(), Ok(&config));
+    let config = Config {
+        fallback: None,
+        ..config
+    };
+    assert!(dotted.serialize(&config).ends_with(",fallback="));
+
+    // Keys that contain a dot are protected.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Opts {
+        opts: BTreeMap<String, String>,
+        #[serde(rename = "x.y")]
+        xy: Solver,
+    }
+
+    let opts = Opts {
+        opts: BTreeMap::from([
+            ("a.b".to_string(), "x".to_string()),
+            ("c".to_string(), "y.z".to_string()),
+        ]),
+        xy: Solver {
+            tol: 1.0,
+            maxiter: 2,
+        },
+    };
+    let s = dotted.serialize(&opts);
+    assert_eq!(s, "opts={{a.b}=x,c=y.z},{x.y}.tol=1,{x.y}.maxiter=2");
+    assert_eq!(dotted.deserialize::<Opts>(&s).as_ref(), Ok(&opts));
+    assert_eq!(
+        to_string(&opts).unwrap(),
+        "opts={a.b=x,c=y.z},x.y={tol=1,maxiter=2}"
+    );
+
+    // Errors.
+    let error = dotted
+        .deserialize::<Config>("name=x,solver.maxiter=1,meshes.1.nelems=2,solver.tol=1")
+        .unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (&ErrorKind::NotAnIndex { expected: 0 }, Some(31..32))
+    );
+    assert_eq!(error.path(), [PathSegment::Key("meshes".into())]);
+    assert_eq!(
+        error.to_string(),
+        "expected index `0` at `meshes` (bytes 31..32)"
+    );
+    let error = dotted
+        .deserialize::<Config>("name.first=x,solver.tol=1,solver.maxiter=1")
+        .unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (&ErrorKind::UnexpectedDottedKeys, Some(0..4))
+    );
+    let error = dotted
+        .deserialize::<Config>("name=x,solver.tol=1,solver.maxiter=-1")
+        .unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (
+            &ErrorKind::NegativeUnsignedInteger { ty: "u32" },
+            Some(35..37)
+        )
+    );
+    assert_eq!(
+        error.path(),
+        [
+            PathSegment::Key("solver".into()),
+            PathSegment::Key("maxiter".into())
+        ]
+    );
+
+    // Deeply nested dotted keys are rejected rather than overflowing the stack.
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    enum Any {
+        Value(serde::de::IgnoredAny),
+    }
+
+    let nested = ["a.".repeat(1000), "a=1".to_string()].concat();
+    assert_eq!(
+        dotted.deserialize::<Any>(&nested).unwrap_err().into_kind(),
+        ErrorKind::NestedTooDeeply
+    );
+    let nested = ["a.".repeat(100), "a=1".to_string()].concat();
+    assert!(dotted.deserialize::<Any>(&nested).is_ok());
+}
+
+#[test]
+fn test_repeated_keys() {
+    use crate::PathSegment;
+    use std::collections::BTreeMap;
+
+    let repeated = Configured {
+        de: |de| de.repeated_keys(true),
+        ser: |ser| ser.repeated_keys(true),
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Args {
+        input: Vec<String>,
+        output: String,
+        #[serde(default)]
+        sizes: Option<(u32, u32)>,
+    }
+
+    let args = Args {
+        input: vec!["a.msh".to_string(), "b,c.msh".to_string()],
+        output: "d.msh".to_string(),
+        sizes: None,
+    };
+    assert_eq!(
+        repeated
+            .deserialize::<Args>("input=a.msh,output=d.msh,input={b,c.msh}")
+            .as_ref(),
+        Ok(&args)
+    );
+    assert_eq!(
+        repeated
+            .deserialize::<Args>("input={a.msh,{b,c.msh}},output=d.msh")
+            .as_ref(),
+        Ok(&args)
+    );
+    assert_eq!(
+        from_str::<Args>("input=a.msh,output=d.msh,input=b.msh").map_err(Error::into_kind),
+        Err(ErrorKind::Message("duplicate field `input`".to_string()))
+    );
+    let args: Args = repeated
+        .deserialize("input=x,output=y,sizes=1,sizes=2")
+        .unwrap();
+    assert_eq!(args.input, ["x"]);
+    assert_eq!(args.sizes, Some((1, 2)));
+    assert_eq!(
+        repeated.deserialize::<BTreeMap<String, Vec<u8>>>("a=1,b=,a=2,{a}=3"),
+        Ok(BTreeMap::from([
+            ("a".to_string(), vec![1, 2, 3]),
+            ("b".to_string(), vec![])
+        ]))
+    );
+    assert_eq!(
+        repeated.deserialize::<BTreeMap<String, Vec<u8>>>("a={1,2},b=3"),
+        Ok(BTreeMap::from([
+            ("a".to_string(), vec![1, 2]),
+            ("b".to_string(), vec![3])
+        ]))
+    );
+
+    // Combined with dotted keys.
+    let de = Deserializer::from_str("opts.input=a,opts.input=b,opts.output=c");
+    let opts: BTreeMap<String, Args> =
+        BTreeMap::deserialize(de.dotted_keys(true).repeated_keys(true)).unwrap();
+    assert_eq!(opts["opts"].input, ["a", "b"]);
+
+    // Round trip through the serializer, which keeps sequences with fewer
+    // than two elements as usual.
+    let args = Args {
+        input: vec!["a.msh".to_string(), "b,c.msh".to_string(), "".to_string()],
+        output: "d.msh".to_string(),
+        sizes: Some((1, 2)),
+    };
+    let s = repeated.serialize(&args);
+    assert_eq!(
+        s,
+        "input=a.msh,input={b,c.msh},input=,output=d.msh,sizes=1,sizes=2"
+    );
+    assert_eq!(repeated.deserialize::<Args>(&s).as_ref(), Ok(&args));
+    let args = Args {
+        input: vec!["a,b".to_string()],
+        sizes: None,
+        ..args
+    };
+    let s = repeated.serialize(&args);
+    assert_eq!(s, "input={{a,b}},output=d.msh,sizes=");
+    assert_eq!(repeated.deserialize::<Args>(&s).as_ref(), Ok(&args));
+
+    // Errors.
+    let error = repeated
+        .deserialize::<Args>("input=x,output=y,output=z")
+        .unwrap_err();
+    assert_eq!(
+        (error.kind(), error.span()),
+        (
+            &ErrorKind::RepeatedKey {
+                key: "output".to_string()
+            },
+            Some(17..23)
+        )
+    );
+    assert_eq!(
+        error.to_string(),
+        "key `output` repeated for a value that is not a sequence at `output` (bytes 17..23)"
+    );
+    let error = repeated
+        .deserialize::<Args>("input=x,output=y,sizes=1,sizes=x")
+        .unwrap_err();
+    assert_eq!(error.span(), Some(31..32));
+    assert_eq!(
+        error.path(),
+        [PathSegment::Key("sizes".into()), PathSegment::Index(1)]
+    );
+}
+
+#[test]
+fn test_duplicate_keys() {
+    use crate::PathSegment;
+    use std::collections::BTreeMap;
+
+    type Map = BTreeMap<String, u32>;
+
+    let strict = Configured {
+        de: |de| de.strict(true),
+        ser: |ser| ser,
+    };
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Config {
+        name: String,
+        #[serde(default)]
+        opts: BTreeMap<String, Map>,
+        #[serde(default)]
+        cache: bool,
+    }
+
+    assert_eq!(
+        from_str::<Map>("a=1,a=2"),
+        Ok(BTreeMap::from([("a".to_string(), 2)]))
+    );
+    assert_eq!(
+        strict.deserialize::<Map>("a=1,b=2").map(|map| map.len()),
+        Ok(2)
+    );
+    let error = strict.deserialize::<Map>("a=1,b=2,{a}=3").unwrap_err();
+    assert_eq!(
+        error.kind(),
+        &ErrorKind::DuplicateKey {
+            key: "a".to_string(),
+            first: 0..1,
+            second: 9..10,
+        }
+    );
+    assert_eq!(error.span(), Some(9..10));
+    let de = Deserializer::from_str("a=1,a=2");
+    assert!(Map::deserialize(de.strict(true).deny_duplicate_keys(false)).is_ok());
+
+    // Nested maps and structs.
+    let error = strict
+        .deserialize::<Config>("name=x,opts={p={a=1,a=2}}")
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "duplicate key `a`, first given at bytes 16..17 at `opts.p` (bytes 20..21)"
+    );
+    assert_eq!(
+        error.path(),
+        [
+            PathSegment::Key("opts".into()),
+            PathSegment::Key("p".into())
+        ]
+    );
+    assert_eq!(
+        strict
+            .deserialize::<Config>("name=x,opts={p={a=1},q={a=2}},name=y")
+            .map_err(Error::into_kind),
+        Err(ErrorKind::DuplicateKey {
+            key: "name".to_string(),
+            first: 0..4,
+            second: 30..34,
+        })
+    );
+    assert_de_error!(
+        Config,
+        "name=x,name=y",
+        ErrorKind::Message("duplicate field `name`".to_string())
+    );
+
+    // Keys of bare flags, dotted keys and repeated keys.
+    let de = Deserializer::from_str("name=x,cache,no-cache").bare_flags(true);
+    assert_eq!(
+        Config::deserialize(de.strict(true)).map_err(Error::into_kind),
+        Err(ErrorKind::DuplicateKey {
+            key: "cache".to_string(),
+            first: 7..12,
+            second: 16..21,
+        })
+    );
+    let s = "name=x,opts.p.a=1,opts.q.a=2,opts.p.a=3";
+    let de = Deserializer::from_str(s).dotted_keys(true);
+    let error = Config::deserialize(de.strict(true)).unwrap_err();
+    assert_eq!((error.span(), error.path().len()), (Some(36..37), 2));
+    let s = "name=x,opts.p.a=1,opts={}";
+    let de = Deserializer::from_str(s).dotted_keys(true);
+    let error = Config::deserialize(de.strict(true)).unwrap_err();
+    assert_eq!(error.span(), Some(18..22));
+    let de = Deserializer::from_str("a=1,b=2,a=3").repeated_keys(true);
+    assert_eq!(
+        BTreeMap::<String, Vec<u32>>::deserialize(de.strict(true)).map(|map| map["a"].len()),
+        Ok(2)
+    );
+}
@@ -1,5 +1,7 @@
 //! Utility functions for generating and parsing Stringly serializations.
 
+use crate::error::{Error, ErrorKind};
+
 /// Created with the function [`safesplit`].
 pub struct SafesplitIter<'a> {
     // string to split
@@ -143,6 +145,20 @@ fn right_balancer_start(s: &str) -> Option<usize> {
     None
 }
 
+// Returns the length of the left (`'<' '{'* '>'`) or right (`'<' '}'* '>'`)
+// balancer at the start of `s`, or `None` if `s` does not start with a
+// balancer.
+pub(crate) fn balancer_len(s: &str) -> Option<usize> {
+    left_balancer_end(s).or_else(|| {
+        let n = s
+            .strip_prefix('<')?
+            .bytes()
+            .take_while(|&ch| ch == b'}')
+            .count();
+        (s.as_bytes().get(n + 1) == Some(&b'>')).then_some(n + 2)
+    })
+}
+
 // Returns `True` if `s` starts with a left balancer (`'<' '{'* '>'`).
 #[inline(always)]
 fn starts_with_balancer(s: &str) -> bool {
@@ -289,7 +305,7 @@ impl ProtectTest for char {
 impl<const N: usize> ProtectTest for [char; N] {
     const UNCONDITIONAL: bool = false;
     fn test(&self, ch: char) -> bool {
-        self.iter().any(|&x| ch == x)
+        self.contains(&ch)
         //ch == self[0] || ch == self[1]
     }
 }
@@ -358,6 +374,258 @@ pub fn splitarg(s: &str) -> Result<(&str, &str), SplitArgError> {
     }
 }
 
+/// Expands a Stringly serialization into an indented, multi-line form.
+///
+/// Every item of the (comma separated) serialization is written on a separate
+/// line. An item that ends with a protected group, e.g. `key={...}` or
+/// `Variant{...}`, is written as the part before the group, e.g. `key=` or
+/// `Variant`, followed by the items of the group, indented by two spaces. An
+/// item that consists of a group only is written as a `-` followed by the
+/// indented items of the group. Groups that are empty or that have items that
+/// cannot be written on a line of their own (empty items or items that start
+/// with a space) are kept inline.
+///
+/// The result is turned back into the original serialization by
+/// [`deprettify`], provided that the serialization contains no newlines and
+/// does not start with a space.
+///
+/// # Examples
+///
+/// ```
+/// let s = "name=solver,args=Gmres{restart=30,tol=1e-8},shape={3,3}";
+/// let pretty = stringly::util::prettify(s);
+/// assert_eq!(
+///     pretty,
+///     "\
+/// name=solver
+/// args=Gmres
+///   restart=30
+///   tol=1e-8
+/// shape=
+///   3
+///   3"
+/// );
+/// assert_eq!(stringly::util::deprettify(&pretty).unwrap(), s);
+/// ```
+pub fn prettify(s: &str) -> String {
+    prettify_width(s, 0)
+}
+
+/// Expands a Stringly serialization into an indented, multi-line form that
+/// keeps groups inline where they fit in the given line width.
+///
+/// Like [`prettify`], but a line, including indentation, that does not exceed
+/// `width` characters is kept as is, without expanding its trailing group. If
+/// the entire serialization fits, the result is the serialization itself.
+/// Lines that cannot be broken may exceed `width`. The result is turned back
+/// into the original serialization by [`deprettify`], under the same
+/// conditions as for [`prettify`].
+///
+/// # Examples
+///
+/// ```
+/// let s = "name=solver,args=Gmres{restart=30,tol=1e-8},shape={3,3}";
+/// let pretty = stringly::util::prettify_width(s, 20);
+/// assert_eq!(
+///     pretty,
+///     "\
+/// name=solver
+/// args=Gmres
+///   restart=30
+///   tol=1e-8
+/// shape={3,3}"
+/// );
+/// assert_eq!(stringly::util::deprettify(&pretty).unwrap(), s);
+/// assert_eq!(stringly::util::prettify_width(s, 80), s);
+/// ```
+pub fn prettify_width(s: &str, width: usize) -> String {
+    if !is_block(s) || s.chars().count() <= width {
+        return s.to_string();
+    }
+    let mut out = String::new();
+    write_block(&mut out, s, 0, width);
+    out
+}
+
+// Returns `true` if every item of `s` can be written on a line of its own.
+fn is_block(s: &str) -> bool {
+    !s.is_empty()
+        && safesplit(s, ',')
+            .all(|item| !item.is_empty() && !item.starts_with(' ') && !item.contains('\n'))
+}
+
+// Writes the items of `s` on separate lines with the given indentation,
+// expanding the trailing groups of items that do not fit in `width`.
+fn write_block(out: &mut String, s: &str, indent: usize, width: usize) {
+    for item in safesplit(s, ',') {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.extend(core::iter::repeat(' ').take(indent));
+        match split_group(item) {
+            Some((head, group))
+                if head != "-" && is_block(group) && indent + item.chars().count() > width =>
+            {
+                out.push_str(if head.is_empty() { "-" } else { head });
+                write_block(out, group, indent + 2, width);
+            }
+            _ => out.push_str(item),
+        }
+    }
+}
+
+// Splits `s` into the part before and the contents of a trailing group, or
+// returns `None` if `s` does not end with a group. Groups with balancers are
+// protected strings rather than items and are not split.
+fn split_group(s: &str) -> Option<(&str, &str)> {
+    let mut level = 0;
+    for (i, ch) in s.char_indices().rev() {
+        if ch == '}' {
+            level += 1;
+        } else if level == 0 {
+            return None;
+        } else if ch == '{' {
+            level -= 1;
+            if level == 0 {
+                let (head, group) = (&s[..i], &s[i + 1..s.len() - 1]);
+                if !is_balanced(head) || starts_with_balancer(group) || ends_with_balancer(group) {
+                    return None;
+                }
+                return Some((head, group));
+            }
+        }
+    }
+    None
+}
+
+/// Collapses the multi-line form generated by [`prettify`] into a single
+/// line.
+///
+/// A line that is followed by lines with a deeper indentation is joined with
+/// the items on those lines, comma separated, enclosed in curly braces. A line
+/// consisting of a `-` only is replaced by the enclosed items. Blank lines are
+/// ignored.
+///
+/// # Errors
+///
+/// Returns [`IndentTooSmall`] if the indentation is increased by a single
+/// space and [`UnmatchedUnindent`] if the indentation is decreased to a level
+/// that does not match any of the outer lines.
+///
+/// [`IndentTooSmall`]: ErrorKind::IndentTooSmall
+/// [`UnmatchedUnindent`]: ErrorKind::UnmatchedUnindent
+///
+/// # Examples
+///
+/// ```
+/// let pretty = "\
+/// name=solver
+/// args=Gmres
+///   restart=30
+///   tol=1e-8
+/// ";
+/// assert_eq!(
+///     stringly::util::deprettify(pretty).unwrap(),
+///     "name=solver,args=Gmres{restart=30,tol=1e-8}"
+/// );
+/// ```
+pub fn deprettify(s: &str) -> Result<String, Error> {
+    deprettify_with_map(s).map(|(out, _)| out)
+}
+
+// A line of the multi-line form: the line number, the indentation, the byte
+// offset of the text in the multi-line form and the text.
+struct Line<'a> {
+    lineno: usize,
+    indent: usize,
+    offset: usize,
+    text: &'a str,
+}
+
+/// Like [`deprettify`], but additionally returns for every line that is copied
+/// into the single-line form the byte offset of its text in the single-line
+/// and the multi-line form and the length of the text, in order of increasing
+/// offset.
+pub(crate) fn deprettify_with_map(s: &str) -> Result<(String, Vec<[usize; 3]>), Error> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for (i, line) in s.split('\n').enumerate() {
+        let text = line.strip_suffix('\r').unwrap_or(line);
+        let trimmed = text.trim_start_matches(' ');
+        if !trimmed.trim().is_empty() {
+            lines.push(Line {
+                lineno: i + 1,
+                indent: text.len() - trimmed.len(),
+                offset: offset + text.len() - trimmed.len(),
+                text: trimmed,
+            });
+        }
+        offset += line.len() + 1;
+    }
+    let mut out = String::new();
+    let mut map = Vec::new();
+    let mut pos = 0;
+    if let Some(first) = lines.first() {
+        write_lines(&lines, &mut pos, first.indent, &mut out, &mut map)?;
+    }
+    match lines.get(pos) {
+        Some(line) => Err(unindent_error(line)),
+        None => Ok((out, map)),
+    }
+}
+
+// Writes the consecutive lines starting at `pos` with the given indentation,
+// comma separated, including the lines with deeper indentation that follow.
+fn write_lines(
+    lines: &[Line],
+    pos: &mut usize,
+    indent: usize,
+    out: &mut String,
+    map: &mut Vec<[usize; 3]>,
+) -> Result<(), Error> {
+    let start = *pos;
+    while let Some(line) = lines.get(*pos) {
+        if line.indent < indent {
+            break;
+        } else if line.indent > indent {
+            return Err(unindent_error(line));
+        }
+        if *pos > start {
+            out.push(',');
+        }
+        *pos += 1;
+        match lines.get(*pos) {
+            Some(next) if next.indent > indent => {
+                if next.indent == indent + 1 {
+                    return Err(Error::from(ErrorKind::IndentTooSmall {
+                        lineno: next.lineno,
+                    })
+                    .at(next.offset - next.indent..next.offset));
+                }
+                if line.text != "-" {
+                    map.push([out.len(), line.offset, line.text.len()]);
+                    out.push_str(line.text);
+                }
+                out.push('{');
+                write_lines(lines, pos, next.indent, out, map)?;
+                out.push('}');
+            }
+            _ => {
+                map.push([out.len(), line.offset, line.text.len()]);
+                out.push_str(line.text);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn unindent_error(line: &Line) -> Error {
+    Error::from(ErrorKind::UnmatchedUnindent {
+        lineno: line.lineno,
+    })
+    .at(line.offset - line.indent..line.offset)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -381,14 +649,14 @@ mod tests {
 
     fn assert_normal(s: &str) {
         let sep = ',';
-        assert_eq!(s.contains(sep), false);
+        assert!(!s.contains(sep));
         assert_protected(s, Some(s), Some(sep));
         assert_protected(s, Some(&["{", s, "}"].concat()), None);
     }
 
     fn assert_normal_unless_unconditional_protection(s: &str, protected: &str) {
         let sep = ',';
-        assert_eq!(s.contains(sep), false);
+        assert!(!s.contains(sep));
         assert_protected(s, Some(s), Some(sep));
         assert_protected(s, Some(protected), None);
     }
@@ -433,7 +701,7 @@ mod tests {
             v
         };
         for length in 0..6 {
-            for i in 0..(4 as usize).pow(length) {
+            for i in 0..4usize.pow(length) {
                 assert_protected(&makestr(i, length, chs1), None, Some(','));
                 assert_protected(&makestr(i, length, chs1), None, None);
                 assert_protected(&makestr(i, length, chs2), None, Some(','));
@@ -517,4 +785,80 @@ mod tests {
             Err(super::SplitArgError::NotAnEnum)
         );
     }
+
+    #[test]
+    fn test_prettify() {
+        use super::{deprettify, prettify, prettify_width};
+
+        assert_eq!(prettify(""), "");
+        assert_eq!(prettify("a"), "a");
+        assert_eq!(prettify("a,b"), "a\nb");
+        assert_eq!(prettify("a={b,c},d"), "a=\n  b\n  c\nd");
+        assert_eq!(prettify("{a,b},{}"), "-\n  a\n  b\n{}");
+        assert_eq!(prettify("A{B{c}}"), "A\n  B\n    c");
+        assert_eq!(prettify("a={,b},-{c}"), "a={,b}\n-{c}");
+        assert_eq!(prettify("a={ b}"), "a={ b}");
+        assert_eq!(prettify("a=1,e={<{>q}"), "a=1\ne={<{>q}");
+        assert_eq!(prettify("a=1,e={<{>}q}"), "a=1\ne={<{>}q}");
+        assert_eq!(prettify("a=1,e={q{<}>}"), "a=1\ne={q{<}>}");
+        assert_eq!(prettify_width("a={b,c},d", 9), "a={b,c},d");
+        assert_eq!(prettify_width("a={b,c},d", 8), "a={b,c}\nd");
+        assert_eq!(prettify_width("a={b,c},d", 6), "a=\n  b\n  c\nd");
+        assert_eq!(
+            prettify_width("a={b={c,d},e={f}}", 10),
+            "a=\n  b={c,d}\n  e={f}"
+        );
+        assert_eq!(
+            prettify_width("a={b={c,d},e={f}}", 8),
+            "a=\n  b=\n    c\n    d\n  e={f}"
+        );
+
+        // Every string that does not start with a space roundtrips.
+        let chars = ['{', '}', ',', '=', '-', ' ', 'a'];
+        let mut s = String::new();
+        for length in 0..7u32 {
+            for i in 0..chars.len().pow(length) {
+                s.clear();
+                let mut k = i;
+                for _j in 0..length {
+                    s.push(chars[k % chars.len()]);
+                    k /= chars.len();
+                }
+                if !s.starts_with(' ') {
+                    for width in 0..8 {
+                        let pretty = prettify_width(&s, width);
+                        assert_eq!(deprettify(&pretty).unwrap(), s, "{:?}", pretty);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_deprettify() {
+        use super::deprettify;
+        use crate::{Error, ErrorKind};
+
+        assert_eq!(deprettify("").unwrap(), "");
+        assert_eq!(deprettify("\n  a\n\n  b\n").unwrap(), "a,b");
+        assert_eq!(deprettify("a=\r\n  b\r\n").unwrap(), "a={b}");
+        assert_eq!(deprettify("a=\n    b\n    c\nd").unwrap(), "a={b,c},d");
+        assert_eq!(deprettify("-\n  a\n  -\n    b").unwrap(), "{a,{b}}");
+        assert_eq!(
+            deprettify("a=\n b").map_err(Error::into_kind),
+            Err(ErrorKind::IndentTooSmall { lineno: 2 })
+        );
+        assert_eq!(
+            deprettify("a=\n    b\n  c").map_err(Error::into_kind),
+            Err(ErrorKind::UnmatchedUnindent { lineno: 3 })
+        );
+        assert_eq!(
+            deprettify("  a\nb").map_err(Error::into_kind),
+            Err(ErrorKind::UnmatchedUnindent { lineno: 2 })
+        );
+        assert_eq!(
+            deprettify("a=\n    b\n  c").unwrap_err().span(),
+            Some(9..11)
+        );
+    }
 }
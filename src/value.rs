@@ -0,0 +1,533 @@
+//! An untyped representation of Stringly data.
+
+use serde::de::{self, Deserialize, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+use crate::de::Deserializer;
+use crate::error::{Error, ErrorKind, Result};
+use crate::ser::to_string;
+use crate::util;
+
+#[cfg(not(feature = "std"))]
+use alloc::borrow::Cow;
+#[cfg(not(feature = "std"))]
+use core::{fmt, marker::PhantomData};
+#[cfg(feature = "std")]
+use std::{borrow::Cow, fmt, marker::PhantomData};
+
+/// A Stringly value of unknown type.
+///
+/// Since Stringly is not self-describing, a `Value` simply holds the
+/// (unprotected) text. The structure of the text is interpreted on demand by
+/// the views [`as_str`], [`as_seq`], [`as_map`] and [`as_variant`], each of
+/// which borrows from the `Value` without copying.
+///
+/// A `Value` can be turned into a concrete type with [`from_value`] or by
+/// using `&Value` as a [`serde::Deserializer`].
+///
+/// [`as_str`]: Value::as_str
+/// [`as_seq`]: Value::as_seq
+/// [`as_map`]: Value::as_map
+/// [`as_variant`]: Value::as_variant
+///
+/// # Examples
+///
+/// ```
+/// let v = stringly::Value::from("a=1,b={x,y}");
+/// let (key, value) = v.as_map().nth(1).unwrap().unwrap();
+/// assert_eq!(key.as_str(), "b");
+/// assert_eq!(value.as_seq().collect::<Vec<_>>(), ["x", "y"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Value<'a> {
+    text: Cow<'a, str>,
+}
+
+impl<'a> Value<'a> {
+    /// Returns the text of this value.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns `true` if the text of this value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Returns an iterator over the items of this value, interpreted as a
+    /// sequence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let v = stringly::Value::from("1,{2,3},{}");
+    /// assert_eq!(v.as_seq().collect::<Vec<_>>(), ["1", "2,3", ""]);
+    /// ```
+    pub fn as_seq(&self) -> Seq<'_> {
+        Seq {
+            iter: util::safesplit(&self.text, ','),
+        }
+    }
+
+    /// Returns an iterator over the key-value pairs of this value, interpreted
+    /// as a mapping.
+    ///
+    /// # Errors
+    ///
+    /// An item yields `Err` if it is not a key-value pair.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let v = stringly::Value::from("a=1,{b=c}=2");
+    /// let pairs: Vec<_> = v.as_map().collect::<stringly::Result<_>>().unwrap();
+    /// assert_eq!(pairs, [("a".into(), "1".into()), ("b=c".into(), "2".into())]);
+    /// ```
+    pub fn as_map(&self) -> Map<'_> {
+        Map {
+            iter: util::safesplit(&self.text, ','),
+        }
+    }
+
+    /// Returns the variant and the (possibly empty) value of this value,
+    /// interpreted as an enum.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the text is not of the form `VARIANT` or
+    /// `VARIANT{VALUE}`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let v = stringly::Value::from("Gmres{restart=30}");
+    /// let (variant, value) = v.as_variant().unwrap();
+    /// assert_eq!(variant, "Gmres");
+    /// assert_eq!(value.as_str(), "restart=30");
+    /// ```
+    pub fn as_variant(&self) -> Result<(&str, Value<'_>)> {
+        let (variant, value) = util::splitarg(&self.text)?;
+        Ok((variant, Value::from(value)))
+    }
+
+    /// Creates a sequence from items.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stringly::Value;
+    /// let v = Value::from_seq([Value::from("1"), Value::from("2,3")]);
+    /// assert_eq!(v.as_str(), "1,{2,3}");
+    /// ```
+    pub fn from_seq<'b, I>(items: I) -> Value<'static>
+    where
+        I: IntoIterator<Item = Value<'b>>,
+    {
+        let mut text = String::new();
+        for (i, item) in items.into_iter().enumerate() {
+            if i != 0 {
+                text.push(',');
+            }
+            if item.is_empty() {
+                text.push_str("{}");
+            } else {
+                text.push_str(&util::protect(item.as_str(), ','));
+            }
+        }
+        Value::from(text)
+    }
+
+    /// Creates a mapping from key-value pairs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stringly::Value;
+    /// let v = Value::from_map([(Value::from("a"), Value::from("1,2"))]);
+    /// assert_eq!(v.as_str(), "a={1,2}");
+    /// ```
+    pub fn from_map<'b, 'c, I>(pairs: I) -> Value<'static>
+    where
+        I: IntoIterator<Item = (Value<'b>, Value<'c>)>,
+    {
+        let mut text = String::new();
+        for (i, (key, value)) in pairs.into_iter().enumerate() {
+            if i != 0 {
+                text.push(',');
+            }
+            text.push_str(&util::protect(key.as_str(), [',', '=']));
+            text.push('=');
+            text.push_str(&util::protect(value.as_str(), ','));
+        }
+        Value::from(text)
+    }
+
+    /// Creates an enum from a variant and a (possibly empty) value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stringly::Value;
+    /// assert_eq!(Value::from_variant("A", &Value::default()).as_str(), "A");
+    /// assert_eq!(Value::from_variant("B", &Value::from("1,2")).as_str(), "B{1,2}");
+    /// ```
+    pub fn from_variant(variant: &str, value: &Value) -> Value<'static> {
+        if value.is_empty() {
+            Value::from(variant.to_string())
+        } else {
+            Value::from([variant, &util::protect_unconditionally(value.as_str())].concat())
+        }
+    }
+
+    /// Converts this value into a value that owns its text.
+    pub fn into_owned(self) -> Value<'static> {
+        Value {
+            text: Cow::Owned(self.text.into_owned()),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(text: &'a str) -> Self {
+        Value {
+            text: Cow::Borrowed(text),
+        }
+    }
+}
+
+impl From<String> for Value<'static> {
+    fn from(text: String) -> Self {
+        Value {
+            text: Cow::Owned(text),
+        }
+    }
+}
+
+impl PartialEq<str> for Value<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Value<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Iterator over the items of a [`Value`], created with [`Value::as_seq`].
+pub struct Seq<'a> {
+    iter: util::SafesplitIter<'a>,
+}
+
+impl<'a> Iterator for Seq<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|s| Value::from(util::unprotect(s)))
+    }
+}
+
+/// Iterator over the key-value pairs of a [`Value`], created with
+/// [`Value::as_map`].
+pub struct Map<'a> {
+    iter: util::SafesplitIter<'a>,
+}
+
+impl<'a> Iterator for Map<'a> {
+    type Item = Result<(Value<'a>, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter
+            .next()
+            .map(|s| match util::safesplit_once(s, '=') {
+                Ok((key, value)) => Ok((
+                    Value::from(util::unprotect(key)),
+                    Value::from(util::unprotect(value)),
+                )),
+                Err(_) => Err(ErrorKind::NotAKeyValuePair.into()),
+            })
+    }
+}
+
+/// Converts an object into a [`Value`].
+pub fn to_value<T>(value: &T) -> Result<Value<'static>>
+where
+    T: Serialize,
+{
+    to_string(value).map(Value::from)
+}
+
+/// Converts a [`Value`] into an object.
+pub fn from_value<'a, T>(value: &'a Value) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    T::deserialize(value)
+}
+
+impl Serialize for Value<'_> {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+// The name of the newtype struct with which a `Value` is requested. The
+// Stringly deserializer responds with the text, other deserializers with the
+// data passed to `ValueVisitor::visit_newtype_struct`.
+pub(crate) const TOKEN: &str = "$stringly::private::Value";
+
+// Visits the text of a `Value`, or, for data that was buffered by Serde, e.g.
+// for flattened fields, the inferred kind, which is serialized back to text.
+struct ValueVisitor<'a>(PhantomData<Value<'a>>);
+
+impl<'de: 'a, 'a> Visitor<'de> for ValueVisitor<'a> {
+    type Value = Value<'a>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> core::result::Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        to_value(&v).map_err(E::custom)
+    }
+
+    fn visit_i64<E>(self, v: i64) -> core::result::Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        to_value(&v).map_err(E::custom)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> core::result::Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        to_value(&v).map_err(E::custom)
+    }
+
+    fn visit_f64<E>(self, v: f64) -> core::result::Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        to_value(&v).map_err(E::custom)
+    }
+
+    fn visit_unit<E>(self) -> core::result::Result<Self::Value, E> {
+        Ok(Value::default())
+    }
+
+    fn visit_none<E>(self) -> core::result::Result<Self::Value, E> {
+        Ok(Value::default())
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> core::result::Result<Self::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> core::result::Result<Self::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> core::result::Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element::<Value>()? {
+            items.push(item);
+        }
+        Ok(Value::from_seq(items))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> core::result::Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut pairs = Vec::new();
+        while let Some(pair) = map.next_entry::<Value, Value>()? {
+            pairs.push(pair);
+        }
+        Ok(Value::from_map(pairs))
+    }
+
+    fn visit_borrowed_str<E>(self, v: &'de str) -> core::result::Result<Self::Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> core::result::Result<Self::Value, E> {
+        Ok(Value::from(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> core::result::Result<Self::Value, E> {
+        Ok(Value::from(v))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Value<'a> {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(TOKEN, ValueVisitor(PhantomData))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for &'de Value<'_> {
+    type Deserializer = Deserializer<&'de str>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        Deserializer::from_str(self.as_str())
+    }
+}
+
+macro_rules! forward_to_deserializer {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value>
+            where
+                V: Visitor<'de>,
+            {
+                de::Deserializer::$method(self.into_deserializer(), $($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for &'de Value<'_> {
+    type Error = Error;
+
+    forward_to_deserializer! {
+        deserialize_any()
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_option()
+        deserialize_unit()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_map()
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+        deserialize_identifier()
+        deserialize_ignored_any()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn test_views() {
+        let v = Value::from("a=1,b={x,{y,z}},c=E{2}");
+        let pairs: Vec<_> = v.as_map().collect::<Result<_>>().unwrap();
+        assert_eq!(pairs[0], (Value::from("a"), Value::from("1")));
+        assert_eq!(pairs[1].1.as_seq().collect::<Vec<_>>(), ["x", "y,z"]);
+        assert_eq!(pairs[2].1.as_variant().unwrap(), ("E", Value::from("2")));
+        assert_eq!(
+            v.as_seq()
+                .map(|item| item.as_str().len())
+                .collect::<Vec<_>>(),
+            [3, 11, 6]
+        );
+        assert_eq!(
+            Value::from("a,b").as_map().next(),
+            Some(Err(ErrorKind::NotAKeyValuePair.into()))
+        );
+        assert_eq!(
+            Value::from("E{").as_variant(),
+            Err(ErrorKind::NotAnEnum.into())
+        );
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let v = Value::from("n=1,opts={tol=1e-6,maxiter=10}");
+        let pairs = v.as_map().map(|pair| {
+            let (key, value) = pair.unwrap();
+            if key == "opts" {
+                let opts = value.as_map().map(|pair| {
+                    let (key, value) = pair.unwrap();
+                    if key == "maxiter" {
+                        (key, Value::from("20"))
+                    } else {
+                        (key, value)
+                    }
+                });
+                (key, Value::from_map(opts))
+            } else {
+                (key, value)
+            }
+        });
+        assert_eq!(
+            Value::from_map(pairs).as_str(),
+            "n=1,opts={tol=1e-6,maxiter=20}"
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Test {
+            int: u32,
+            seq: Vec<String>,
+        }
+
+        let test = Test {
+            int: 1,
+            seq: vec!["a".to_owned(), "b,c".to_owned()],
+        };
+        let v = to_value(&test).unwrap();
+        assert_eq!(v.as_str(), "int=1,seq={a,{b,c}}");
+        assert_eq!(from_value::<Test>(&v).unwrap(), test);
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Generic<'a> {
+            int: u32,
+            #[serde(borrow)]
+            rest: Value<'a>,
+        }
+
+        let generic: Generic = crate::from_str("int=1,rest={a,b}").unwrap();
+        assert_eq!(generic.rest.as_seq().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(to_string(&generic).unwrap(), "int=1,rest={a,b}");
+    }
+}
@@ -0,0 +1,401 @@
+//! Adapters for `#[serde(with = "...")]`.
+//!
+//! The modules in here serialize and deserialize types whose Serde
+//! representation is awkward to write by hand as short strings with units.
+//!
+//! # Examples
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use std::time::Duration;
+//!
+//! #[derive(Deserialize, Serialize, PartialEq, Debug)]
+//! struct Cache {
+//!     #[serde(with = "stringly::with::duration")]
+//!     ttl: Duration,
+//!     #[serde(with = "stringly::with::bytesize")]
+//!     capacity: u64,
+//! }
+//!
+//! let cache: Cache = stringly::from_str("ttl=2h30m,capacity=64KiB").unwrap();
+//! assert_eq!(cache.ttl, Duration::from_secs(9000));
+//! assert_eq!(cache.capacity, 65536);
+//! assert_eq!(stringly::to_string(&cache).unwrap(), "ttl=2h30m,capacity=64KiB");
+//! ```
+
+use serde::de::{self, Deserialize, Deserializer};
+
+use crate::value::Value;
+
+// Deserializes the text of a value and parses it with `parse`, failing with
+// `expected` as the message. Going through `Value` recovers the text of
+// buffered values, e.g. in flattened fields, that were inferred as numbers.
+fn deserialize_parsed<'de, D, T>(
+    deserializer: D,
+    parse: fn(&str) -> Option<T>,
+    expected: &str,
+) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    parse(value.as_str()).ok_or_else(|| de::Error::custom(format_args!("expected {}", expected)))
+}
+
+// Parses a nonnegative decimal number with optional fractional part,
+// multiplied by `unit`, rounding down. Returns `None` on overflow.
+fn parse_scaled(number: &str, unit: u128) -> Option<u128> {
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    let digits = |s: &str| s.bytes().all(|ch| ch.is_ascii_digit());
+    if int.is_empty() && frac.is_empty() || !digits(int) || !digits(frac) {
+        return None;
+    }
+    let mut v = if int.is_empty() {
+        0
+    } else {
+        int.parse::<u128>().ok()?
+    };
+    v = v.checked_mul(unit)?;
+    // Digits beyond the 19th contribute less than one for units below 1e19.
+    let frac = &frac[..frac.len().min(19)];
+    if !frac.is_empty() {
+        let numerator: u128 = frac.parse().unwrap();
+        v = v.checked_add(numerator * unit / 10u128.pow(frac.len() as u32))?;
+    }
+    Some(v)
+}
+
+/// Serializes [`Duration`](core::time::Duration) as a string with units.
+///
+/// A duration is a sum of nonnegative numbers, each directly followed by one
+/// of the units `d`, `h`, `m`, `s`, `ms`, `us` (or `µs`) and `ns`, for
+/// example `250ms`, `1.5s` or `2h30m`. A number may end in a decimal point,
+/// as in `1.s`. Zero may also be written without a unit. Durations are
+/// serialized as hours and minutes followed by seconds, or by the largest
+/// fitting unit below a second, with a fractional part if needed: `2h30m`,
+/// `1m1.5s`, `250ms`.
+///
+/// # Examples
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use std::time::Duration;
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct Timeout(#[serde(with = "stringly::with::duration")] Duration);
+///
+/// let timeout: Timeout = stringly::from_str("1.5s").unwrap();
+/// assert_eq!(timeout.0, Duration::from_millis(1500));
+/// let timeout = Timeout(Duration::from_micros(2500));
+/// assert_eq!(stringly::to_string(&timeout).unwrap(), "2.5ms");
+/// ```
+pub mod duration {
+    use super::{deserialize_parsed, parse_scaled};
+    use core::time::Duration;
+    use serde::{Deserializer, Serializer};
+
+    const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+    const UNITS: [(&str, u128); 8] = [
+        ("d", 86400 * NANOS_PER_SEC),
+        ("h", 3600 * NANOS_PER_SEC),
+        ("m", 60 * NANOS_PER_SEC),
+        ("s", NANOS_PER_SEC),
+        ("ms", 1_000_000),
+        ("us", 1_000),
+        ("µs", 1_000),
+        ("ns", 1),
+    ];
+
+    /// Serializes a duration.
+    pub fn serialize<S: Serializer>(v: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(*v))
+    }
+
+    /// Deserializes a duration.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        deserialize_parsed(
+            deserializer,
+            parse,
+            "a duration like `250ms`, `1.5s` or `2h30m`",
+        )
+    }
+
+    pub(crate) fn format(v: Duration) -> String {
+        let mut s = String::new();
+        let secs = v.as_secs();
+        let (h, m) = (secs / 3600, secs / 60 % 60);
+        if h > 0 {
+            s.push_str(&format!("{}h", h));
+        }
+        if m > 0 {
+            s.push_str(&format!("{}m", m));
+        }
+        let (rest, nanos) = (secs % 60, v.subsec_nanos());
+        if rest > 0 {
+            s.push_str(&format!("{}", rest));
+            push_fraction(&mut s, nanos, 9);
+            s.push('s');
+        } else if nanos > 0 {
+            // The largest unit below a second with a nonzero integer part.
+            let (unit, digits) = match nanos {
+                1_000_000.. => ("ms", 6),
+                1_000.. => ("us", 3),
+                _ => ("ns", 0),
+            };
+            let scale = 10u32.pow(digits);
+            s.push_str(&format!("{}", nanos / scale));
+            push_fraction(&mut s, nanos % scale, digits);
+            s.push_str(unit);
+        } else if s.is_empty() {
+            s.push_str("0s");
+        }
+        s
+    }
+
+    // Appends `frac` as a fractional part with `digits` digits, without
+    // trailing zeros.
+    fn push_fraction(s: &mut String, frac: u32, digits: u32) {
+        if frac > 0 {
+            let frac = format!("{:0width$}", frac, width = digits as usize);
+            s.push('.');
+            s.push_str(frac.trim_end_matches('0'));
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Option<Duration> {
+        if s.is_empty() {
+            return None;
+        } else if s == "0" {
+            return Some(Duration::ZERO);
+        }
+        let mut total = 0u128;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let number = rest.find(|ch: char| !ch.is_ascii_digit() && ch != '.')?;
+            let unit = rest[number..]
+                .find(|ch: char| ch.is_ascii_digit() || ch == '.')
+                .map_or(rest.len(), |len| number + len);
+            let &(_, nanos) = UNITS
+                .iter()
+                .find(|(name, _)| *name == &rest[number..unit])?;
+            total = total.checked_add(parse_scaled(&rest[..number], nanos)?)?;
+            rest = &rest[unit..];
+        }
+        let secs = u64::try_from(total / NANOS_PER_SEC).ok()?;
+        Some(Duration::new(secs, (total % NANOS_PER_SEC) as u32))
+    }
+}
+
+/// Serializes a number of bytes as a string with units.
+///
+/// A byte size is a nonnegative number, optionally followed by one of the
+/// units `B`, the decimal units `kB` (or `KB`), `MB`, `GB`, `TB`, `PB` and
+/// `EB`, or the binary units `KiB`, `MiB`, `GiB`, `TiB`, `PiB` and `EiB`, for
+/// example `64KiB` or `2GB`. A fractional number of bytes is rounded down. A
+/// byte size is serialized as an integer followed by the unit that gives the
+/// shortest text: `64KiB`, `2GB`, `1001B`.
+///
+/// # Examples
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct Limit(#[serde(with = "stringly::with::bytesize")] u64);
+///
+/// let limit: Limit = stringly::from_str("1.5GiB").unwrap();
+/// assert_eq!(limit.0, 3 << 29);
+/// assert_eq!(stringly::to_string(&limit).unwrap(), "1536MiB");
+/// ```
+pub mod bytesize {
+    use super::{deserialize_parsed, parse_scaled};
+    use serde::{Deserializer, Serializer};
+
+    const UNITS: [(&str, u128); 14] = [
+        ("B", 1),
+        ("kB", 1_000),
+        ("KB", 1_000),
+        ("MB", 1_000_000),
+        ("GB", 1_000_000_000),
+        ("TB", 1_000_000_000_000),
+        ("PB", 1_000_000_000_000_000),
+        ("EB", 1_000_000_000_000_000_000),
+        ("KiB", 1 << 10),
+        ("MiB", 1 << 20),
+        ("GiB", 1 << 30),
+        ("TiB", 1 << 40),
+        ("PiB", 1 << 50),
+        ("EiB", 1 << 60),
+    ];
+
+    /// Serializes a byte size.
+    pub fn serialize<S: Serializer>(v: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(*v))
+    }
+
+    /// Deserializes a byte size.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        deserialize_parsed(deserializer, parse, "a byte size like `64KiB` or `2GB`")
+    }
+
+    pub(crate) fn format(v: u64) -> String {
+        UNITS
+            .iter()
+            .filter(|&&(name, unit)| name != "KB" && v as u128 % unit == 0)
+            .map(|&(name, unit)| format!("{}{}", v as u128 / unit, name))
+            // The first of the shortest, preferring smaller units.
+            .fold(None::<String>, |best, s| match best {
+                Some(best) if best.len() <= s.len() => Some(best),
+                _ => Some(s),
+            })
+            .unwrap()
+    }
+
+    pub(crate) fn parse(s: &str) -> Option<u64> {
+        let number = s
+            .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+            .unwrap_or(s.len());
+        let unit = match &s[number..] {
+            "" => 1,
+            name => UNITS.iter().find(|(unit, _)| *unit == name)?.1,
+        };
+        parse_scaled(&s[..number], unit)?.try_into().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+
+    #[test]
+    fn test_duration() {
+        for (s, v) in [
+            ("0s", Duration::ZERO),
+            ("250ms", Duration::from_millis(250)),
+            ("1.5s", Duration::from_millis(1500)),
+            ("2h30m", Duration::from_secs(9000)),
+            ("1m1.5s", Duration::from_millis(61500)),
+            ("48h", Duration::from_secs(172800)),
+            ("1h1ns", Duration::new(3600, 1)),
+            ("2.5ms", Duration::from_micros(2500)),
+            ("1.001us", Duration::from_nanos(1001)),
+            ("7ns", Duration::from_nanos(7)),
+        ] {
+            assert_eq!(duration::format(v), s);
+            assert_eq!(duration::parse(s), Some(v));
+        }
+        assert_eq!(duration::parse("0"), Some(Duration::ZERO));
+        assert_eq!(duration::parse("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(duration::parse("1h90m"), Some(Duration::from_secs(9000)));
+        assert_eq!(duration::parse(".5s"), Some(Duration::from_millis(500)));
+        assert_eq!(duration::parse("3µs"), Some(Duration::from_micros(3)));
+        assert_eq!(duration::parse("1.0000000009s"), Some(Duration::new(1, 0)));
+        assert_eq!(duration::parse("1.s"), Some(Duration::from_secs(1)));
+        assert_eq!(duration::parse("1.m30s"), Some(Duration::from_secs(90)));
+        for s in [
+            "", "1", "s", "1x", "1.s.", "1s.", ".s", "0.", "..5s", "-1s", "1 s", "1s2",
+        ] {
+            assert_eq!(duration::parse(s), None, "{}", s);
+        }
+        assert_eq!(duration::parse("1e30d"), None);
+        assert_eq!(duration::parse("999999999999999999999d"), None);
+    }
+
+    #[test]
+    fn test_bytesize() {
+        for (s, v) in [
+            ("0B", 0),
+            ("1B", 1),
+            ("1kB", 1000),
+            ("1001B", 1001),
+            ("64KiB", 65536),
+            ("2GB", 2_000_000_000),
+            ("1536MiB", 3 << 29),
+            ("1EiB", 1 << 60),
+            ("18446744073709551615B", u64::MAX),
+        ] {
+            assert_eq!(bytesize::format(v), s);
+            assert_eq!(bytesize::parse(s), Some(v));
+        }
+        assert_eq!(bytesize::parse("42"), Some(42));
+        assert_eq!(bytesize::parse("1kB"), Some(1000));
+        assert_eq!(bytesize::parse("1KB"), Some(1000));
+        assert_eq!(bytesize::parse("1.5KiB"), Some(1536));
+        assert_eq!(bytesize::parse("0.1B"), Some(0));
+        assert_eq!(bytesize::parse("2.KiB"), Some(2048));
+        assert_eq!(bytesize::parse("2."), Some(2));
+        for s in [
+            "", ".", "KiB", ".KiB", "1kib", "1 KiB", "1.2.3B", "-1B", "16EiB",
+        ] {
+            assert_eq!(bytesize::parse(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        use crate::{from_str, ErrorKind};
+        use serde::Deserialize;
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Cache {
+            #[serde(with = "duration")]
+            ttl: Duration,
+            #[serde(with = "bytesize")]
+            capacity: u64,
+        }
+
+        let error = from_str::<Cache>("ttl=5 min,capacity=1KiB").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected a duration like `250ms`, `1.5s` or `2h30m` at `ttl` (bytes 4..9)"
+        );
+        let error = from_str::<Cache>("ttl=5m,capacity=1 KB").unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &ErrorKind::Message("expected a byte size like `64KiB` or `2GB`".to_string())
+        );
+        assert_eq!(error.span(), Some(16..20));
+    }
+
+    #[test]
+    fn test_inferred() {
+        use crate::from_str;
+        use serde::Deserialize;
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Cache {
+            #[serde(with = "duration")]
+            ttl: Duration,
+            #[serde(with = "bytesize")]
+            capacity: u64,
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Config {
+            name: String,
+            #[serde(flatten)]
+            cache: Cache,
+        }
+
+        let cache = |ttl, capacity| Config {
+            name: "a".to_string(),
+            cache: Cache { ttl, capacity },
+        };
+        assert_eq!(
+            from_str::<Config>("name=a,ttl=0,capacity=42").unwrap(),
+            cache(Duration::ZERO, 42)
+        );
+        assert_eq!(
+            from_str::<Config>("name=a,ttl=1s,capacity=1KiB").unwrap(),
+            cache(Duration::from_secs(1), 1024)
+        );
+        assert_eq!(
+            from_str::<Config>("name=a,ttl=0,capacity=1.5").unwrap(),
+            cache(Duration::ZERO, 1)
+        );
+        assert!(from_str::<Config>("name=a,ttl=5,capacity=42").is_err());
+    }
+}